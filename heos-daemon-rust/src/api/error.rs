@@ -0,0 +1,125 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use heos_daemon_rust::error::{HeosError, HeosErrorCode};
+use serde_json::json;
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+// wraps `HeosError` so it can be turned into a response.
+pub struct ApiError(HeosError);
+
+impl From<HeosError> for ApiError {
+    fn from(err: HeosError) -> Self {
+        ApiError(err)
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match self.0 {
+            HeosError::InvalidCommand(message) => {
+                (status_for(&message.eid), Json(message)).into_response()
+            }
+            HeosError::Timeout { .. } => (
+                StatusCode::GATEWAY_TIMEOUT,
+                Json(json!({ "text": self.0.to_string() })),
+            )
+                .into_response(),
+            HeosError::NoDevicesFound => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(json!({ "text": "no devices found" })),
+            )
+                .into_response(),
+            other => (
+                StatusCode::BAD_GATEWAY,
+                // the alternate format includes the causes.
+                Json(json!({ "text": format!("{:#}", other) })),
+            )
+                .into_response(),
+        }
+    }
+}
+
+fn status_for(eid: &HeosErrorCode) -> StatusCode {
+    match eid {
+        HeosErrorCode::InvalidId | HeosErrorCode::UserNotFound => StatusCode::NOT_FOUND,
+        HeosErrorCode::UnrecognizedCommand
+        | HeosErrorCode::WrongNumberOfArguments
+        | HeosErrorCode::ParameterOutOfRange
+        | HeosErrorCode::OptionNotSupported => StatusCode::BAD_REQUEST,
+        HeosErrorCode::InvalidCredentials | HeosErrorCode::UserNotLoggedIn => {
+            StatusCode::UNAUTHORIZED
+        }
+        HeosErrorCode::RequestedDataNotAvailable => StatusCode::NOT_FOUND,
+        HeosErrorCode::MediaCantBePlayed => StatusCode::UNPROCESSABLE_ENTITY,
+        HeosErrorCode::ResourceCurrentlyNotAvailable | HeosErrorCode::ProcessingPreviousCommand => {
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        HeosErrorCode::CommandCouldNitBeExecuted
+        | HeosErrorCode::InternalError
+        | HeosErrorCode::SystemError
+        | HeosErrorCode::Unknown => StatusCode::BAD_GATEWAY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use heos_daemon_rust::error::ErrorMessage;
+    use std::time::Duration;
+
+    #[test]
+    fn statuses_for_error_codes() {
+        let cases = [
+            (HeosErrorCode::InvalidId, StatusCode::NOT_FOUND),
+            (HeosErrorCode::UserNotFound, StatusCode::NOT_FOUND),
+            (HeosErrorCode::ParameterOutOfRange, StatusCode::BAD_REQUEST),
+            (HeosErrorCode::UnrecognizedCommand, StatusCode::BAD_REQUEST),
+            (HeosErrorCode::UserNotLoggedIn, StatusCode::UNAUTHORIZED),
+            (
+                HeosErrorCode::MediaCantBePlayed,
+                StatusCode::UNPROCESSABLE_ENTITY,
+            ),
+            (
+                HeosErrorCode::ProcessingPreviousCommand,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ),
+            (HeosErrorCode::SystemError, StatusCode::BAD_GATEWAY),
+            (HeosErrorCode::Unknown, StatusCode::BAD_GATEWAY),
+        ];
+        for (eid, status) in cases {
+            assert_eq!(status_for(&eid), status, "{:?}", eid);
+        }
+    }
+
+    #[test]
+    fn statuses_for_errors() {
+        let timeout = HeosError::Timeout {
+            command: "player/get_volume".to_owned(),
+            after: Duration::from_secs(10),
+        };
+        let cases = [
+            (timeout, StatusCode::GATEWAY_TIMEOUT),
+            (HeosError::NoDevicesFound, StatusCode::SERVICE_UNAVAILABLE),
+            (
+                HeosError::InvalidCommand(ErrorMessage {
+                    eid: HeosErrorCode::InvalidId,
+                    text: "ID Not Valid".to_owned(),
+                    context: None,
+                    command: None,
+                    syserrno: None,
+                    sequence: None,
+                }),
+                StatusCode::NOT_FOUND,
+            ),
+            (
+                anyhow::anyhow!("connection reset").into(),
+                StatusCode::BAD_GATEWAY,
+            ),
+        ];
+        for (err, status) in cases {
+            assert_eq!(ApiError(err).into_response().status(), status);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::convert::Infallible;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, State};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Response;
+use heos_daemon_rust::{HeosEvent, PlayerId};
+use serde::Deserialize;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, warn};
+
+use super::AppState;
+
+// `?event=player_volume_changed,player_state_changed&pid=1`, both optional.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct EventFilter {
+    event: Option<String>,
+    pid: Option<PlayerId>,
+}
+
+impl EventFilter {
+    fn matches(&self, event: &HeosEvent) -> bool {
+        let name_matches = match &self.event {
+            Some(names) => names.split(',').any(|name| name.trim() == event.name()),
+            None => true,
+        };
+        let pid_matches = match self.pid {
+            Some(pid) => event.pid() == Some(pid),
+            None => true,
+        };
+        name_matches && pid_matches
+    }
+}
+
+fn filtered_events(state: &AppState, filter: EventFilter) -> impl Stream<Item = HeosEvent> {
+    state
+        .client
+        .events()
+        .filter(move |event| filter.matches(event))
+}
+
+/// Server-sent events, one `data:` line of json per event named after it.
+pub async fn sse(
+    State(state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = filtered_events(&state, filter).filter_map(|event| {
+        match Event::default().event(event.name()).json_data(&event) {
+            Ok(sse_event) => Some(Ok(sse_event)),
+            Err(err) => {
+                warn!("could not serialize {:?}: {}", event, err);
+                None
+            }
+        }
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// A websocket sending every event as a json text message.
+pub async fn websocket(
+    State(state): State<AppState>,
+    Query(filter): Query<EventFilter>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    upgrade.on_upgrade(move |socket| forward_events(socket, state, filter))
+}
+
+async fn forward_events(mut socket: WebSocket, state: AppState, filter: EventFilter) {
+    let mut events = Box::pin(filtered_events(&state, filter));
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let event = match event {
+                    Some(event) => event,
+                    None => break,
+                };
+                let json = match serde_json::to_string(&event) {
+                    Ok(json) => json,
+                    Err(err) => {
+                        warn!("could not serialize {:?}: {}", event, err);
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            // we only listen to notice the client going away.
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    debug!("websocket closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use heos_daemon_rust::mock::MockHeos;
+    use heos_daemon_rust::{HeosClient, OnOrOff, PlayState};
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn filter(event: Option<&str>, pid: Option<PlayerId>) -> EventFilter {
+        EventFilter {
+            event: event.map(str::to_owned),
+            pid,
+        }
+    }
+
+    fn volume_changed(pid: PlayerId) -> HeosEvent {
+        HeosEvent::PlayerVolumeChanged {
+            pid,
+            level: 40,
+            mute: OnOrOff::Off,
+        }
+    }
+
+    #[test]
+    fn everything_matches_without_a_filter() {
+        let everything = EventFilter::default();
+        assert!(everything.matches(&volume_changed(1)));
+        assert!(everything.matches(&HeosEvent::PlayersChanged));
+    }
+
+    #[test]
+    fn filter_by_name() {
+        let names = filter(Some("player_volume_changed, player_state_changed"), None);
+        assert!(names.matches(&volume_changed(1)));
+        let state_changed = HeosEvent::PlayerStateChanged {
+            pid: 2,
+            state: PlayState::Play,
+        };
+        assert!(names.matches(&state_changed));
+        assert!(!names.matches(&HeosEvent::PlayersChanged));
+    }
+
+    #[test]
+    fn filter_by_pid() {
+        let player = filter(None, Some(1));
+        assert!(player.matches(&volume_changed(1)));
+        assert!(!player.matches(&volume_changed(2)));
+        // events about no player in particular don't match a pid.
+        assert!(!player.matches(&HeosEvent::PlayersChanged));
+        assert!(!player.matches(&HeosEvent::Reconnected));
+
+        let both = filter(Some("players_changed"), Some(1));
+        assert!(!both.matches(&HeosEvent::PlayersChanged));
+        assert!(!both.matches(&volume_changed(1)));
+    }
+
+    #[tokio::test]
+    async fn events_reach_the_stream() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = HeosClient::connect(mock.address()).await.unwrap();
+        let request = Request::get("/events?event=player_volume_changed&pid=1")
+            .body(Body::empty())
+            .unwrap();
+        // the stream ends with the client.
+        let response = crate::api::router(client.clone())
+            .oneshot(request)
+            .await
+            .unwrap();
+        let mut body = response.into_body();
+
+        mock.send_event("player_volume_changed", "pid=2&level=10&mute=off");
+        mock.send_event("player_volume_changed", "pid=1&level=40&mute=off");
+        let frame = tokio::time::timeout(Duration::from_secs(2), body.frame())
+            .await
+            .expect("no event in time")
+            .unwrap()
+            .unwrap();
+        let data = frame.into_data().unwrap();
+        let text = std::str::from_utf8(&data).unwrap();
+        assert!(
+            text.starts_with("event: player_volume_changed\n"),
+            "{}",
+            text
+        );
+        assert!(text.contains("\"pid\":1"), "{}", text);
+        assert!(text.contains("\"level\":40"), "{}", text);
+    }
+}
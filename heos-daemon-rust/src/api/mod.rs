@@ -0,0 +1,292 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use heos_daemon_rust::{
+    AccountStatus, CheckAccount, GetGroupVolume, GetGroups, GetNowPlayingMedia, GetPlayState,
+    GetPlayerInfo, GetPlayerVolume, GetPlayers, GetQueue, GroupCommand, GroupId, GroupInfo,
+    HeosClient, HeosResult, Level, NowPlaying, PlayState, PlayerCommand, PlayerId, PlayerInfo,
+    QueueItem, Range,
+};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tracing::info;
+
+mod error;
+mod events;
+use error::ApiResult;
+
+#[derive(Clone)]
+struct AppState {
+    client: HeosClient,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Volume {
+    level: Level,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayStateBody {
+    state: PlayState,
+}
+
+// `?start=0&end=9`, both are needed to page.
+#[derive(Deserialize)]
+struct RangeQuery {
+    start: Option<u32>,
+    end: Option<u32>,
+}
+
+impl RangeQuery {
+    fn range(&self) -> Option<Range> {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => Some(Range { start, end }),
+            _ => None,
+        }
+    }
+}
+
+pub fn router(client: HeosClient) -> Router {
+    Router::new()
+        .route("/players", get(get_players))
+        .route("/players/:pid", get(get_player))
+        .route("/players/:pid/volume", get(get_volume).put(set_volume))
+        .route(
+            "/players/:pid/play_state",
+            get(get_play_state).post(set_play_state),
+        )
+        .route("/players/:pid/now_playing", get(get_now_playing))
+        .route("/players/:pid/queue", get(get_queue))
+        .route("/groups", get(get_groups))
+        .route(
+            "/groups/:gid/volume",
+            get(get_group_volume).put(set_group_volume),
+        )
+        .route("/account", get(get_account))
+        .route("/events", get(events::sse))
+        .route("/events/ws", get(events::websocket))
+        .with_state(AppState { client })
+}
+
+pub async fn serve<A: ToSocketAddrs>(address: A, client: HeosClient) -> HeosResult<()> {
+    let listener = TcpListener::bind(address).await?;
+    info!("serving http api on {}", listener.local_addr()?);
+    axum::serve(listener, router(client)).await?;
+    Ok(())
+}
+
+async fn get_players(State(state): State<AppState>) -> ApiResult<Json<Vec<PlayerInfo>>> {
+    Ok(Json(state.client.execute_command(GetPlayers).await?))
+}
+
+async fn get_player(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+) -> ApiResult<Json<PlayerInfo>> {
+    Ok(Json(
+        state.client.execute_command(GetPlayerInfo { pid }).await?,
+    ))
+}
+
+async fn get_volume(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+) -> ApiResult<Json<Volume>> {
+    let level = state
+        .client
+        .execute_command(GetPlayerVolume { pid })
+        .await?;
+    Ok(Json(Volume { level }))
+}
+
+async fn set_volume(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+    Json(volume): Json<Volume>,
+) -> ApiResult<StatusCode> {
+    state
+        .client
+        .execute_command(PlayerCommand::SetPlayerVolume {
+            pid,
+            level: volume.level,
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_play_state(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+) -> ApiResult<Json<PlayStateBody>> {
+    let play_state = state.client.execute_command(GetPlayState { pid }).await?;
+    Ok(Json(PlayStateBody { state: play_state }))
+}
+
+async fn set_play_state(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+    Json(body): Json<PlayStateBody>,
+) -> ApiResult<StatusCode> {
+    state
+        .client
+        .execute_command(PlayerCommand::SetPlayState {
+            pid,
+            state: body.state,
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_now_playing(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+) -> ApiResult<Json<NowPlaying>> {
+    Ok(Json(
+        state
+            .client
+            .execute_command(GetNowPlayingMedia { pid })
+            .await?,
+    ))
+}
+
+async fn get_queue(
+    State(state): State<AppState>,
+    Path(pid): Path<PlayerId>,
+    Query(query): Query<RangeQuery>,
+) -> ApiResult<Json<Vec<QueueItem>>> {
+    let range = query.range();
+    Ok(Json(
+        state
+            .client
+            .execute_command(GetQueue { pid, range })
+            .await?,
+    ))
+}
+
+async fn get_groups(State(state): State<AppState>) -> ApiResult<Json<Vec<GroupInfo>>> {
+    Ok(Json(state.client.execute_command(GetGroups).await?))
+}
+
+async fn get_group_volume(
+    State(state): State<AppState>,
+    Path(gid): Path<GroupId>,
+) -> ApiResult<Json<Volume>> {
+    let level = state.client.execute_command(GetGroupVolume { gid }).await?;
+    Ok(Json(Volume { level }))
+}
+
+async fn set_group_volume(
+    State(state): State<AppState>,
+    Path(gid): Path<GroupId>,
+    Json(volume): Json<Volume>,
+) -> ApiResult<StatusCode> {
+    state
+        .client
+        .execute_command(GroupCommand::SetGroupVolume {
+            gid,
+            level: volume.level,
+        })
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn get_account(State(state): State<AppState>) -> ApiResult<Json<AccountStatus>> {
+    Ok(Json(state.client.execute_command(CheckAccount).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, Method, Request};
+    use heos_daemon_rust::mock::{MockHeos, MockReply};
+    use http_body_util::BodyExt;
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
+
+    async fn app(mock: &MockHeos) -> Router {
+        router(HeosClient::connect(mock.address()).await.unwrap())
+    }
+
+    async fn send(
+        app: Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = Request::builder().method(method).uri(uri);
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        let response = app.oneshot(request.unwrap()).await.unwrap();
+        let status = response.status();
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let json = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, json)
+    }
+
+    fn sent(mock: &MockHeos, command: &str) -> bool {
+        mock.received().iter().any(|line| line.starts_with(command))
+    }
+
+    #[tokio::test]
+    async fn volume() {
+        let mock = MockHeos::start().await.unwrap();
+        let (status, json) = send(app(&mock).await, Method::GET, "/players/1/volume", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json, json!({ "level": 25 }));
+
+        let body = Some(json!({ "level": 40 }));
+        let (status, _) = send(app(&mock).await, Method::PUT, "/players/1/volume", body).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(sent(&mock, "player/set_volume?pid=1&level=40"));
+    }
+
+    #[tokio::test]
+    async fn play_state() {
+        let mock = MockHeos::start().await.unwrap();
+        let body = Some(json!({ "state": "play" }));
+        let (status, _) = send(
+            app(&mock).await,
+            Method::POST,
+            "/players/1/play_state",
+            body,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(sent(&mock, "player/set_play_state?pid=1&state=play"));
+    }
+
+    #[tokio::test]
+    async fn queue_window() {
+        let mock = MockHeos::start().await.unwrap();
+        let uri = "/players/1/queue?start=10&end=19";
+        let (status, json) = send(app(&mock).await, Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json, json!([]));
+        assert!(sent(&mock, "player/get_queue?pid=1&range=10,19"));
+
+        // without both ends the whole queue is asked for.
+        let uri = "/players/1/queue?start=10";
+        send(app(&mock).await, Method::GET, uri, None).await;
+        let received = mock.received();
+        assert!(received
+            .last()
+            .unwrap()
+            .starts_with("player/get_queue?pid=1"));
+        assert!(!received.last().unwrap().contains("range="));
+    }
+
+    #[tokio::test]
+    async fn device_errors() {
+        let mock = MockHeos::start().await.unwrap();
+        mock.on("player/get_volume", MockReply::failure(2, "ID Not Valid"));
+        let (status, json) = send(app(&mock).await, Method::GET, "/players/7/volume", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json["text"], "ID Not Valid");
+    }
+}
@@ -0,0 +1,239 @@
+use std::time::Duration;
+
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use heos_daemon_rust::discovery::{self, HEOS_PORT};
+use heos_daemon_rust::resolve::Resolver;
+use heos_daemon_rust::{
+    ClientOptions, CommandPayload, GetNowPlayingMedia, GetPlayerVolume, GetPlayers, GetQueue,
+    GroupCommand, HeosClient, HeosResult, NowPlayingMedia, PlayState, PlayerCommand,
+};
+use pretty_env_logger::env_logger;
+use serde::Serialize;
+use serde_json::Value;
+use tokio_stream::StreamExt;
+
+/// Quick one-offs against a HEOS system.
+#[derive(Parser)]
+#[command(name = "heosctl")]
+struct Cli {
+    /// Address of a HEOS device, discovered via SSDP if omitted.
+    #[arg(long, global = true, env = "HEOS_HOST")]
+    host: Option<String>,
+
+    /// Print json instead of text.
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List all players.
+    Players,
+    /// Show the volume, or change it to `level`, `+N` or `-N`.
+    Volume {
+        player: String,
+        #[arg(allow_hyphen_values = true)]
+        change: Option<String>,
+    },
+    /// Start playing.
+    Play { player: String },
+    /// Pause playing.
+    Pause { player: String },
+    /// Stop playing.
+    Stop { player: String },
+    /// Show what a player is playing.
+    NowPlaying { player: String },
+    /// Group the members with the leader; no members ungroups the leader.
+    Group {
+        leader: String,
+        members: Vec<String>,
+    },
+    /// Show the queue of a player.
+    Queue { player: String },
+    /// Print events until interrupted.
+    Events,
+    /// Send any command, e.g. `heos://player/get_players`.
+    Raw { command: String },
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("warn"));
+    if let Err(err) = run(Cli::parse()).await {
+        // the alternate format includes the causes.
+        eprintln!("heosctl: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn run(cli: Cli) -> HeosResult<()> {
+    let client = connect(cli.host.as_deref()).await?;
+    let resolver = Resolver::new(client.clone());
+    let json = cli.json;
+    match cli.command {
+        Command::Players => {
+            let players = client.execute_command(GetPlayers).await?;
+            print(json, &players, |players| {
+                for player in players {
+                    println!("{}\t{}\t{}", player.pid, player.name, player.model);
+                }
+            });
+        }
+        Command::Volume { player, change } => {
+            let pid = resolver.player(&player).await?;
+            let current = client.execute_command(GetPlayerVolume { pid }).await?;
+            let level = match change.as_deref() {
+                None => current,
+                Some(change) => {
+                    let level = new_level(current, change)?;
+                    client
+                        .execute_command(PlayerCommand::SetPlayerVolume { pid, level })
+                        .await?;
+                    level
+                }
+            };
+            print(json, &level, |level| println!("{}", level));
+        }
+        Command::Play { player } => {
+            set_play_state(&client, &resolver, &player, PlayState::Play).await?
+        }
+        Command::Pause { player } => {
+            set_play_state(&client, &resolver, &player, PlayState::Pause).await?
+        }
+        Command::Stop { player } => {
+            set_play_state(&client, &resolver, &player, PlayState::Stop).await?
+        }
+        Command::NowPlaying { player } => {
+            let pid = resolver.player(&player).await?;
+            let now_playing = client.execute_command(GetNowPlayingMedia { pid }).await?;
+            print(json, &now_playing, |now_playing| match &now_playing.media {
+                Some(NowPlayingMedia::Station {
+                    station,
+                    song,
+                    artist,
+                    ..
+                }) => println!("{}: {} - {}", station, artist, song),
+                Some(media) => {
+                    println!("{} - {} ({})", media.artist(), media.song(), media.album())
+                }
+                None => println!("nothing playing"),
+            });
+        }
+        Command::Group { leader, members } => {
+            let leader = resolver.player(&leader).await?;
+            let mut pids = Vec::new();
+            for member in &members {
+                pids.push(resolver.player(member).await?);
+            }
+            client
+                .execute_command(GroupCommand::SetGroup {
+                    leader,
+                    members: pids,
+                })
+                .await?;
+        }
+        Command::Queue { player } => {
+            let pid = resolver.player(&player).await?;
+            let queue = client
+                .execute_command(GetQueue { pid, range: None })
+                .await?;
+            print(json, &queue, |queue| {
+                for item in queue {
+                    println!("{}\t{} - {}", item.qid, item.artist, item.song);
+                }
+            });
+        }
+        Command::Events => {
+            let mut events = Box::pin(client.events());
+            while let Some(event) = events.next().await {
+                print(json, &event, |event| println!("{:?}", event));
+            }
+        }
+        Command::Raw { command } => {
+            let response = client.execute_raw(CommandPayload::raw(&command)).await?;
+            // there is no better text form for an arbitrary response.
+            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+        }
+    }
+    Ok(())
+}
+
+async fn connect(host: Option<&str>) -> HeosResult<HeosClient> {
+    let options = ClientOptions {
+        heartbeat: None,
+        ..ClientOptions::default()
+    };
+    match host {
+        Some(host) if host.contains(':') => HeosClient::connect_with(host, options).await,
+        Some(host) => HeosClient::connect_with((host, HEOS_PORT), options).await,
+        None => {
+            let device = discovery::discover(Duration::from_secs(3)).await?;
+            HeosClient::connect_with(device.heos_address(), options).await
+        }
+    }
+}
+
+async fn set_play_state(
+    client: &HeosClient,
+    resolver: &Resolver,
+    player: &str,
+    state: PlayState,
+) -> HeosResult<()> {
+    let pid = resolver.player(player).await?;
+    client
+        .execute_command(PlayerCommand::SetPlayState { pid, state })
+        .await?;
+    Ok(())
+}
+
+// `42` sets the level, `+5` and `-5` change it relative to `current`.
+fn new_level(current: u8, change: &str) -> HeosResult<u8> {
+    let parsed: Result<i16, _> = change.trim_start_matches('+').parse();
+    let value = parsed.map_err(|_| anyhow!("invalid volume {}", change))?;
+    let level = if change.starts_with('+') || change.starts_with('-') {
+        current as i16 + value
+    } else {
+        value
+    };
+    Ok(level.clamp(0, 100) as u8)
+}
+
+fn print<T: Serialize, F: FnOnce(&T)>(json: bool, value: &T, text: F) {
+    if json {
+        match serde_json::to_value(value) {
+            Ok(Value::Null) => {}
+            Ok(value) => println!("{}", value),
+            Err(err) => eprintln!("could not serialize output: {}", err),
+        }
+    } else {
+        text(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::new_level;
+
+    #[test]
+    fn volume_changes() {
+        let cases = [
+            (25, "+5", 30),
+            (25, "-5", 20),
+            (25, "42", 42),
+            (25, "0", 0),
+            (98, "+5", 100),
+            (3, "-5", 0),
+            (25, "150", 100),
+        ];
+        for (current, change, expected) in cases {
+            assert_eq!(new_level(current, change).unwrap(), expected, "{}", change);
+        }
+        for change in ["", "+", "loud", "5%"] {
+            assert!(new_level(25, change).is_err(), "{}", change);
+        }
+    }
+}
@@ -0,0 +1,945 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::net::ToSocketAddrs;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::time::{Instant, Interval};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, info, warn};
+
+use crate::{
+    CommandResponse, Connection, Frame, HeosError, HeosEvent, HeosResult, OnOrOff, Sequence,
+    SystemCommand,
+};
+
+use super::{timed, CommandPayload, HeosRequest};
+
+const COMMAND_BUFFER: usize = 32;
+const EVENT_BUFFER: usize = 256;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Reply = oneshot::Sender<HeosResult<CommandResponse>>;
+
+struct PendingCommand {
+    command: CommandPayload,
+    reply: Reply,
+}
+
+// a command sent and not answered yet.
+struct Sent {
+    name: String,
+    waiter: Waiter,
+    at: Instant,
+}
+
+// somebody waiting for a response.
+enum Waiter {
+    Caller(Reply),
+    // remembered once the device accepted it.
+    SignIn(Reply, CommandPayload),
+    Heartbeat(Instant),
+}
+
+impl Waiter {
+    fn fail(self, err: HeosError) {
+        match self {
+            Waiter::Caller(reply) | Waiter::SignIn(reply, _) => {
+                let _ = reply.send(Err(err));
+            }
+            Waiter::Heartbeat(_) => {}
+        }
+    }
+
+    // the caller gave up waiting.
+    fn is_abandoned(&self) -> bool {
+        match self {
+            Waiter::Caller(reply) | Waiter::SignIn(reply, _) => reply.is_closed(),
+            Waiter::Heartbeat(_) => false,
+        }
+    }
+}
+
+/// How long to wait between reconnect attempts. The delay doubles after
+/// every failed attempt until it reaches `max`.
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Sends `system/heart_beat` every `interval`. The connection is considered
+/// dead once `max_missed` beats are still unanswered.
+#[derive(Clone, Copy, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub max_missed: u32,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            interval: Duration::from_secs(15),
+            max_missed: 3,
+        }
+    }
+}
+
+/// How often a command refused with a transient error (see
+/// `HeosError::is_retryable`) is sent again, waiting `delay` before each try.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            retries: 0,
+            delay: Duration::ZERO,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 3,
+            delay: Duration::from_millis(250),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct ClientOptions {
+    pub backoff: Backoff,
+    // no keepalive at all if `None`.
+    pub heartbeat: Option<Heartbeat>,
+    pub retry: RetryPolicy,
+    // how long a command may take including retries, forever if `None`.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            backoff: Backoff::default(),
+            heartbeat: Some(Heartbeat::default()),
+            retry: RetryPolicy::default(),
+            timeout: Some(DEFAULT_TIMEOUT),
+        }
+    }
+}
+
+/// The state of the connection as seen by the heartbeat.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Health {
+    pub healthy: bool,
+    // round trip of the last answered heartbeat.
+    pub latency: Option<Duration>,
+    pub missed_beats: u32,
+}
+
+impl Health {
+    fn connected() -> Health {
+        Health {
+            healthy: true,
+            latency: None,
+            missed_beats: 0,
+        }
+    }
+}
+
+/// A cloneable handle to a `Connection` driven by a background task.
+///
+/// Any number of tasks can execute commands at the same time; every command
+/// is tagged with a `SEQUENCE` and its response routed back by the echoed
+/// sequence. Events are broadcast to every subscriber instead of being dropped.
+#[derive(Clone, Debug)]
+pub struct HeosClient {
+    commands: mpsc::Sender<PendingCommand>,
+    events: broadcast::Sender<HeosEvent>,
+    health: watch::Receiver<Health>,
+    retry: RetryPolicy,
+    timeout: Option<Duration>,
+}
+
+// what the background task needs to know to reconnect.
+struct Supervisor {
+    address: SocketAddr,
+    backoff: Backoff,
+}
+
+impl HeosClient {
+    /// Connects to a device and keeps the connection alive.
+    ///
+    /// The client registers for change events and, whenever the connection
+    /// drops, reconnects, registers again, signs in again if a `SignIn` was
+    /// accepted before and finally sends a `HeosEvent::Reconnected` to every
+    /// subscriber. Commands executed while reconnecting fail right away.
+    pub async fn connect<T: ToSocketAddrs>(s: T) -> HeosResult<HeosClient> {
+        Self::connect_with(s, ClientOptions::default()).await
+    }
+
+    pub async fn connect_with<T: ToSocketAddrs>(
+        s: T,
+        options: ClientOptions,
+    ) -> HeosResult<HeosClient> {
+        let mut connection = Connection::connect(s).await?;
+        let address = connection.peer_addr()?;
+        restore(&mut connection, None, options.timeout).await?;
+        let supervisor = Supervisor {
+            address,
+            backoff: options.backoff,
+        };
+        Ok(Self::spawn(connection, options, Some(supervisor)))
+    }
+
+    /// Takes over the connection and spawns the task reading from it.
+    ///
+    /// Unlike `connect` this doesn't reconnect; the client stops working
+    /// once the connection is gone.
+    pub fn new(connection: Connection, options: ClientOptions) -> HeosClient {
+        Self::spawn(connection, options, None)
+    }
+
+    fn spawn(
+        connection: Connection,
+        options: ClientOptions,
+        supervisor: Option<Supervisor>,
+    ) -> HeosClient {
+        let (commands, receiver) = mpsc::channel(COMMAND_BUFFER);
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let (health_sender, health) = watch::channel(Health::connected());
+        let task = Task {
+            commands: receiver,
+            events: events.clone(),
+            health: health_sender,
+            heartbeat: options.heartbeat,
+            supervisor,
+            timeout: options.timeout,
+            sign_in: None,
+            sequence: 0,
+        };
+        tokio::spawn(task.supervise(connection));
+        HeosClient {
+            commands,
+            events,
+            health,
+            retry: options.retry,
+            timeout: options.timeout,
+        }
+    }
+
+    /// Executes a command and parses its answer into the command's response type.
+    pub async fn execute_command<R: HeosRequest>(&self, command: R) -> HeosResult<R::Response> {
+        let response = self.execute_raw(command).await?;
+        R::parse_response(response)
+    }
+
+    /// Like `execute_command` but with its own timeout instead of the one
+    /// from the client's `ClientOptions`.
+    pub async fn execute_command_with_timeout<R: HeosRequest>(
+        &self,
+        command: R,
+        timeout: Duration,
+    ) -> HeosResult<R::Response> {
+        let response = self.execute_raw_with_timeout(command, timeout).await?;
+        R::parse_response(response)
+    }
+
+    /// Executes any command and returns the response as sent by the device.
+    ///
+    /// Transient failures are retried according to the client's `RetryPolicy`.
+    /// Fails with `HeosError::Timeout` if there is no answer in time; a late
+    /// answer is dropped once it arrives.
+    pub async fn execute_raw<T: Into<CommandPayload>>(
+        &self,
+        command: T,
+    ) -> HeosResult<CommandResponse> {
+        let command = command.into();
+        timed(&command.to_string(), self.timeout, self.retrying(&command)).await
+    }
+
+    pub async fn execute_raw_with_timeout<T: Into<CommandPayload>>(
+        &self,
+        command: T,
+        timeout: Duration,
+    ) -> HeosResult<CommandResponse> {
+        let command = command.into();
+        timed(&command.to_string(), Some(timeout), self.retrying(&command)).await
+    }
+
+    async fn retrying(&self, command: &CommandPayload) -> HeosResult<CommandResponse> {
+        let mut retries = self.retry.retries;
+        loop {
+            match self.send(command.clone()).await {
+                Err(err) if err.is_retryable() && retries > 0 => {
+                    debug!("retrying {}: {}", command.command_name(), err);
+                    retries -= 1;
+                    tokio::time::sleep(self.retry.delay).await;
+                }
+                Err(HeosError::InvalidCommand(mut message)) => {
+                    message.command = Some(command.to_string());
+                    return Err(HeosError::InvalidCommand(message));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn send(&self, command: CommandPayload) -> HeosResult<CommandResponse> {
+        let (reply, response) = oneshot::channel();
+        let command = PendingCommand { command, reply };
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| anyhow!("connection to heos device is closed"))?;
+        response
+            .await
+            .map_err(|_| anyhow!("connection to heos device closed before the response"))?
+    }
+
+    /// A stream of all events received from now on.
+    ///
+    /// Subscribers that fall too far behind miss the oldest events rather
+    /// than holding up everybody else.
+    pub fn events(&self) -> impl Stream<Item = HeosEvent> {
+        BroadcastStream::new(self.events.subscribe()).filter_map(|event| match event {
+            Ok(event) => Some(event),
+            Err(err) => {
+                warn!("event subscriber lagging: {}", err);
+                None
+            }
+        })
+    }
+
+    /// The latest heartbeat latency and whether the connection is usable.
+    pub fn health(&self) -> Health {
+        *self.health.borrow()
+    }
+
+    /// True as long as the task driving the connection is running.
+    pub fn is_connected(&self) -> bool {
+        !self.commands.is_closed()
+    }
+}
+
+// why `Task::run` returned.
+enum Stopped {
+    ConnectionLost,
+    HandlesDropped,
+}
+
+// the background task owning the connection.
+struct Task {
+    commands: mpsc::Receiver<PendingCommand>,
+    events: broadcast::Sender<HeosEvent>,
+    health: watch::Sender<Health>,
+    heartbeat: Option<Heartbeat>,
+    supervisor: Option<Supervisor>,
+    timeout: Option<Duration>,
+    // remembered to sign in again after reconnecting.
+    sign_in: Option<CommandPayload>,
+    // the sequence of the last command sent.
+    sequence: Sequence,
+}
+
+impl Task {
+    async fn supervise(mut self, mut connection: Connection) {
+        loop {
+            let stopped = self.run(&mut connection).await;
+            self.health.send_modify(|health| health.healthy = false);
+            if let Stopped::HandlesDropped = stopped {
+                return;
+            }
+            connection = match self.reconnect().await {
+                Some(connection) => connection,
+                None => return,
+            };
+            let _ = self.health.send(Health::connected());
+            let _ = self.events.send(HeosEvent::Reconnected);
+        }
+    }
+
+    async fn run(&mut self, connection: &mut Connection) -> Stopped {
+        // go on counting where the connection stopped, so a late answer to
+        // a command it sent itself, e.g. in `restore`, isn't taken for ours.
+        self.sequence = connection.sequence;
+        let mut pending: BTreeMap<Sequence, Sent> = BTreeMap::new();
+        let mut beats = self
+            .heartbeat
+            .map(|beat| tokio::time::interval_at(Instant::now() + beat.interval, beat.interval));
+        let stopped = loop {
+            tokio::select! {
+                frame = connection.read_frame() => match frame {
+                    Ok(Some(frame)) => self.dispatch(frame, &mut pending),
+                    Ok(None) => {
+                        info!("heos device closed the connection");
+                        break Stopped::ConnectionLost;
+                    }
+                    Err(err) => {
+                        warn!("reading from heos device failed: {}", err);
+                        break Stopped::ConnectionLost;
+                    }
+                },
+                command = self.commands.recv() => match command {
+                    // the caller gave up before the command was sent.
+                    Some(PendingCommand { command, reply }) if reply.is_closed() => {
+                        debug!("not sending cancelled {}", command);
+                    }
+                    Some(PendingCommand { command, reply }) => {
+                        self.forget_abandoned(&mut pending);
+                        let name = command.command_name().to_owned();
+                        let waiter = match name.as_str() {
+                            "system/sign_in" => Waiter::SignIn(reply, command.clone()),
+                            "system/sign_out" => {
+                                self.sign_in = None;
+                                Waiter::Caller(reply)
+                            }
+                            _ => Waiter::Caller(reply),
+                        };
+                        let sequence = self.next_sequence();
+                        match connection.write_command(command.with_sequence(sequence)).await {
+                            // kept for a while even if the caller gives up,
+                            // see `forget_abandoned`.
+                            Ok(()) => {
+                                let at = Instant::now();
+                                pending.insert(sequence, Sent { name, waiter, at });
+                            }
+                            Err(err) => waiter.fail(err),
+                        }
+                    }
+                    // every handle is gone, nobody is left to answer to.
+                    None => break Stopped::HandlesDropped,
+                },
+                _ = tick(&mut beats) => {
+                    self.forget_abandoned(&mut pending);
+                    let beat: CommandPayload = SystemCommand::HeartBeat.into();
+                    let missed = pending
+                        .values()
+                        .filter(|sent| matches!(sent.waiter, Waiter::Heartbeat(_)))
+                        .count() as u32;
+                    self.health.send_modify(|health| health.missed_beats = missed);
+                    if self.heartbeat.is_some_and(|beat| missed >= beat.max_missed) {
+                        warn!("missed {} heartbeats, giving up on the connection", missed);
+                        break Stopped::ConnectionLost;
+                    }
+                    let name = beat.command_name().to_owned();
+                    let sequence = self.next_sequence();
+                    match connection.write_command(beat.with_sequence(sequence)).await {
+                        Ok(()) => {
+                            let at = Instant::now();
+                            let waiter = Waiter::Heartbeat(at);
+                            pending.insert(sequence, Sent { name, waiter, at });
+                        }
+                        Err(err) => {
+                            warn!("could not send heartbeat: {}", err);
+                            break Stopped::ConnectionLost;
+                        }
+                    }
+                },
+            }
+        };
+        // dropping the pending replies fails every waiting caller.
+        debug!(
+            "stopped heos connection with {} pending commands",
+            pending.len()
+        );
+        stopped
+    }
+
+    fn dispatch(&mut self, frame: Frame, pending: &mut BTreeMap<Sequence, Sent>) {
+        match frame {
+            Frame::UnderProcess(name) => debug!("waiting for {}", name),
+            Frame::Response(response) => {
+                let name = response.command_name.clone();
+                let sequence = response.sequence;
+                self.reply(pending, &name, sequence, Ok(response));
+            }
+            Frame::Error(error) => {
+                let name = error.context.clone().unwrap_or_default();
+                let sequence = error.sequence;
+                self.reply(
+                    pending,
+                    &name,
+                    sequence,
+                    Err(HeosError::InvalidCommand(error)),
+                );
+            }
+            Frame::Event(event) => {
+                // an error only means there is no subscriber right now.
+                let _ = self.events.send(event);
+            }
+        }
+    }
+
+    fn reply(
+        &mut self,
+        pending: &mut BTreeMap<Sequence, Sent>,
+        name: &str,
+        sequence: Option<Sequence>,
+        result: HeosResult<CommandResponse>,
+    ) {
+        let oldest = pending
+            .iter()
+            .find(|(_, sent)| sent.name == name)
+            .map(|(sequence, _)| *sequence);
+        let sent = match sequence {
+            Some(sequence) if pending.contains_key(&sequence) => pending.remove(&sequence),
+            // sent on this connection, but nobody is waiting for it anymore.
+            Some(sequence) if sequence <= self.sequence => {
+                debug!(
+                    "dropping late answer to {} with sequence {}",
+                    name, sequence
+                );
+                return;
+            }
+            // nothing was sent with that sequence, so the answer got mixed up
+            // and whoever waits for this command won't get a proper one.
+            Some(sequence) => {
+                warn!("got {} with unknown sequence {}", name, sequence);
+                if let Some(sent) = oldest.and_then(|oldest| pending.remove(&oldest)) {
+                    sent.waiter.fail(
+                        anyhow!(
+                            "got an answer to {} with unexpected sequence {}",
+                            name,
+                            sequence
+                        )
+                        .into(),
+                    );
+                }
+                return;
+            }
+            // without an echoed sequence the oldest command of that name is answered.
+            None => oldest.and_then(|oldest| pending.remove(&oldest)),
+        };
+        let waiter = match sent {
+            Some(sent) if sent.name != name => {
+                let expected = sent.name;
+                warn!("got an answer to {} for {}", name, expected);
+                sent.waiter.fail(
+                    anyhow!("got an answer to {} while waiting for {}", name, expected).into(),
+                );
+                return;
+            }
+            sent => sent.map(|sent| sent.waiter),
+        };
+        match waiter {
+            // the caller may have given up waiting, that's fine.
+            Some(Waiter::Caller(reply)) => {
+                let _ = reply.send(result);
+            }
+            Some(Waiter::SignIn(reply, command)) => {
+                if result.is_ok() {
+                    self.sign_in = Some(command);
+                }
+                let _ = reply.send(result);
+            }
+            // any answer means the device is still there.
+            Some(Waiter::Heartbeat(sent)) => self.health.send_modify(|health| {
+                health.latency = Some(sent.elapsed());
+                health.missed_beats = 0;
+            }),
+            None => warn!("dropping unexpected response for {}", name),
+        }
+    }
+
+    // drops the commands whose caller gave up at least a timeout ago. Until
+    // then an answer without a sequence still finds the command it belongs
+    // to; later ones are dropped as late answers anyway.
+    fn forget_abandoned(&self, pending: &mut BTreeMap<Sequence, Sent>) {
+        let keep = self.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        pending.retain(|sequence, sent| {
+            let forget = sent.waiter.is_abandoned() && sent.at.elapsed() >= keep;
+            if forget {
+                debug!(
+                    "forgetting abandoned {} with sequence {}",
+                    sent.name, sequence
+                );
+            }
+            !forget
+        });
+    }
+
+    fn next_sequence(&mut self) -> Sequence {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    async fn reconnect(&mut self) -> Option<Connection> {
+        let supervisor = self.supervisor.as_ref()?;
+        let mut delay = supervisor.backoff.initial;
+        loop {
+            let wait = tokio::time::sleep(delay);
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+                    command = self.commands.recv() => match command {
+                        Some(PendingCommand { reply, .. }) => {
+                            let _ = reply.send(Err(anyhow!("reconnecting to heos device").into()));
+                        }
+                        None => return None,
+                    },
+                }
+            }
+            match Connection::connect(supervisor.address).await {
+                Ok(mut connection) => {
+                    let sign_in = self.sign_in.as_ref();
+                    match restore(&mut connection, sign_in, self.timeout).await {
+                        Ok(()) => {
+                            info!("reconnected to heos device at {}", supervisor.address);
+                            return Some(connection);
+                        }
+                        Err(err) => warn!("could not restore connection: {}", err),
+                    }
+                }
+                Err(err) => warn!("reconnecting to {} failed: {}", supervisor.address, err),
+            }
+            delay = (delay * 2).min(supervisor.backoff.max);
+        }
+    }
+}
+
+// waits for the next beat, or forever without a heartbeat.
+async fn tick(beats: &mut Option<Interval>) {
+    match beats {
+        Some(beats) => {
+            beats.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+// brings a new connection into the state the client had before.
+async fn restore(
+    connection: &mut Connection,
+    sign_in: Option<&CommandPayload>,
+    timeout: Option<Duration>,
+) -> HeosResult<()> {
+    let register = SystemCommand::RegisterForChangeEvents {
+        enable: OnOrOff::On,
+    };
+    connection
+        .execute_raw_with_timeout(register, timeout)
+        .await?;
+    if let Some(sign_in) = sign_in {
+        // wrong credentials are no reason to drop the connection again.
+        if let Err(err) = connection
+            .execute_raw_with_timeout(sign_in.clone(), timeout)
+            .await
+        {
+            warn!("could not sign in again: {}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HeosErrorCode;
+    use crate::mock::{MockHeos, MockReply};
+    use crate::{AccountStatus, GetPlayerVolume, GetPlayers, PlayerCommand, PlayerInfo, SignIn};
+
+    async fn connect(mock: &MockHeos) -> HeosClient {
+        let options = ClientOptions {
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+            },
+            ..ClientOptions::default()
+        };
+        HeosClient::connect_with(mock.address(), options)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn executes_commands_and_broadcasts_events() {
+        let mock = MockHeos::start().await.unwrap();
+        mock.on("player/get_volume", MockReply::message("level=42"));
+        let client = connect(&mock).await;
+        let mut events = Box::pin(client.events());
+
+        let players = client.execute_command(GetPlayers).await.unwrap();
+        assert_eq!(players.len(), 2);
+        let level = client.execute_command(GetPlayerVolume { pid: 1 }).await;
+        assert_eq!(level.unwrap(), 42);
+
+        mock.send_event("players_changed", "");
+        assert!(matches!(
+            events.next().await,
+            Some(HeosEvent::PlayersChanged)
+        ));
+        assert!(mock.received()[0].starts_with("system/register_for_change_events?enable=on"));
+    }
+
+    #[tokio::test]
+    async fn enum_commands_answer_typed_or_raw() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = connect(&mock).await;
+        let players: Vec<PlayerInfo> = client
+            .execute_command(PlayerCommand::GetPlayers)
+            .await
+            .unwrap();
+        assert_eq!(players.len(), 2);
+        let raw = client
+            .execute_command(PlayerCommand::GetPlayerVolume { pid: 1 })
+            .await
+            .unwrap();
+        assert_eq!(raw.message["level"], 25);
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_answer_after_under_process() {
+        let mock = MockHeos::start().await.unwrap();
+        let reply = MockReply::message("level=42").under_process();
+        mock.on("player/get_volume", reply);
+        let client = connect(&mock).await;
+        let level = client.execute_command(GetPlayerVolume { pid: 1 }).await;
+        assert_eq!(level.unwrap(), 42);
+    }
+
+    #[tokio::test]
+    async fn failures_keep_their_code() {
+        let mock = MockHeos::start().await.unwrap();
+        mock.on("player/get_volume", MockReply::failure(2, "ID Not Valid"));
+        let client = connect(&mock).await;
+        match client.execute_command(GetPlayerVolume { pid: 9 }).await {
+            Err(HeosError::InvalidCommand(message)) => {
+                assert_eq!(message.eid, HeosErrorCode::InvalidId);
+                assert_eq!(message.text, "ID Not Valid");
+                let command = message.command.as_deref();
+                assert_eq!(command, Some("heos://player/get_volume?pid=9"));
+                assert_eq!(message.syserrno, None);
+            }
+            other => panic!("expected a failure, got {:?}", other),
+        }
+
+        let failure = MockReply::failure(12, "System error").with_syserrno(-9);
+        mock.on("player/get_volume", failure);
+        match client.execute_command(GetPlayerVolume { pid: 1 }).await {
+            Err(HeosError::InvalidCommand(message)) => {
+                assert_eq!(message.eid, HeosErrorCode::SystemError);
+                assert_eq!(message.syserrno, Some(-9));
+            }
+            other => panic!("expected a failure, got {:?}", other),
+        }
+    }
+
+    fn volume_requests(mock: &MockHeos) -> usize {
+        let received = mock.received();
+        received
+            .iter()
+            .filter(|line| line.starts_with("player/get_volume"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = connect(&mock).await;
+        let busy = MockReply::failure(13, "Processing previous command");
+        mock.once("player/get_volume", busy);
+        let level = client.execute_command(GetPlayerVolume { pid: 1 }).await;
+        assert_eq!(level.unwrap(), 25);
+        assert_eq!(volume_requests(&mock), 2);
+
+        let unavailable = MockReply::failure(5, "Resource currently not available");
+        mock.once("player/get_volume", unavailable);
+        let level = client.execute_command(GetPlayerVolume { pid: 1 }).await;
+        assert_eq!(level.unwrap(), 25);
+        assert_eq!(volume_requests(&mock), 4);
+    }
+
+    #[tokio::test]
+    async fn other_failures_are_not_retried() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = connect(&mock).await;
+        mock.once("player/get_volume", MockReply::failure(2, "ID Not Valid"));
+        let level = client.execute_command(GetPlayerVolume { pid: 9 }).await;
+        assert!(level.is_err());
+        assert_eq!(volume_requests(&mock), 1);
+    }
+
+    #[tokio::test]
+    async fn restores_the_connection_after_a_drop() {
+        let mock = MockHeos::start().await.unwrap();
+        mock.on("system/sign_in", MockReply::message("signed_in"));
+        let client = connect(&mock).await;
+        let sign_in = SignIn {
+            un: "heos@example.com".to_owned(),
+            pw: "secret".to_owned(),
+        };
+        let status = client.execute_command(sign_in).await.unwrap();
+        assert_eq!(
+            status,
+            AccountStatus::SignedIn("heos@example.com".to_owned())
+        );
+        let mut events = Box::pin(client.events());
+
+        mock.disconnect_all();
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next()).await;
+        assert!(matches!(event, Ok(Some(HeosEvent::Reconnected))));
+
+        let received = mock.received();
+        let sent = |name: &str| {
+            received
+                .iter()
+                .filter(|command| command.starts_with(name))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(sent("system/register_for_change_events?enable=on").len(), 2);
+        let sign_ins = sent("system/sign_in?un=heos@example.com&pw=secret");
+        assert_eq!(sign_ins.len(), 2);
+        let level = client.execute_command(GetPlayerVolume { pid: 1 }).await;
+        assert_eq!(level.unwrap(), 25);
+    }
+
+    #[tokio::test]
+    async fn missed_heartbeats_drop_the_connection() {
+        assert!(ClientOptions::default().heartbeat.is_some());
+        let mock = MockHeos::start().await.unwrap();
+        let options = ClientOptions {
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+            },
+            heartbeat: Some(Heartbeat {
+                interval: Duration::from_millis(20),
+                max_missed: 3,
+            }),
+            ..ClientOptions::default()
+        };
+        let client = HeosClient::connect_with(mock.address(), options)
+            .await
+            .unwrap();
+        let health_until = |done: fn(&Health) -> bool| {
+            let client = client.clone();
+            let wait = async move {
+                while !done(&client.health()) {
+                    tokio::time::sleep(Duration::from_millis(2)).await;
+                }
+            };
+            tokio::time::timeout(Duration::from_secs(2), wait)
+        };
+        // answered beats tell the latency.
+        health_until(|health| health.latency.is_some())
+            .await
+            .expect("no heartbeat was answered");
+        assert_eq!(client.health().missed_beats, 0);
+
+        mock.on("system/heart_beat", MockReply::Silent);
+        let mut events = Box::pin(client.events());
+        health_until(|health| health.missed_beats > 0)
+            .await
+            .expect("missed heartbeats weren't counted");
+        let event = tokio::time::timeout(Duration::from_secs(5), events.next()).await;
+        assert!(matches!(event, Ok(Some(HeosEvent::Reconnected))));
+        assert!(mock
+            .received()
+            .iter()
+            .any(|command| command.starts_with("system/heart_beat")));
+    }
+
+    // holds back the answer to the first `system/sign_in` until the next
+    // one arrives.
+    async fn late_sign_in_device() -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+            let (socket, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = socket.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            let mut held = None;
+            while let Ok(Some(line)) = lines.next_line().await {
+                let command = line.trim().trim_start_matches("heos://").to_owned();
+                let (name, params) = command.split_once('?').unwrap_or((&command, ""));
+                let answer = format!(
+                    "{{\"heos\": {{\"command\": \"{}\", \"result\": \"success\", \"message\": \"signed_in&{}\"}}}}\r\n",
+                    name, params
+                );
+                if name == "system/sign_in" && held.is_none() {
+                    held = Some(answer);
+                    continue;
+                }
+                if let Some(late) = held.take() {
+                    writer.write_all(late.as_bytes()).await.unwrap();
+                }
+                writer.write_all(answer.as_bytes()).await.unwrap();
+            }
+        });
+        address
+    }
+
+    #[tokio::test]
+    async fn late_answers_from_before_the_client_are_dropped() {
+        let address = late_sign_in_device().await;
+        let mut connection = Connection::connect(address).await.unwrap();
+        let first = SignIn {
+            un: "first".to_owned(),
+            pw: "secret".to_owned(),
+        };
+        let timeout = Duration::from_millis(50);
+        let err = connection
+            .execute_command_with_timeout(first, timeout)
+            .await;
+        assert!(matches!(err, Err(HeosError::Timeout { .. })));
+
+        let client = HeosClient::new(connection, ClientOptions::default());
+        let second = SignIn {
+            un: "second".to_owned(),
+            pw: "secret".to_owned(),
+        };
+        let status = client.execute_command(second).await.unwrap();
+        assert_eq!(status, AccountStatus::SignedIn("second".to_owned()));
+    }
+
+    #[test]
+    fn abandoned_commands_are_forgotten() {
+        let (_, commands) = mpsc::channel(1);
+        let (events, _) = broadcast::channel(1);
+        let (health, _) = watch::channel(Health::connected());
+        let timeout = Duration::from_secs(1);
+        let task = Task {
+            commands,
+            events,
+            health,
+            heartbeat: None,
+            supervisor: None,
+            timeout: Some(timeout),
+            sign_in: None,
+            sequence: 3,
+        };
+        let sent = |waiter, ago| Sent {
+            name: "player/get_volume".to_owned(),
+            waiter,
+            at: Instant::now() - ago,
+        };
+        let (old, abandoned) = oneshot::channel();
+        let (recent, abandoned_recently) = oneshot::channel();
+        let (waiting, _response) = oneshot::channel();
+        drop((abandoned, abandoned_recently));
+        let mut pending = BTreeMap::new();
+        pending.insert(1, sent(Waiter::Caller(old), timeout * 2));
+        pending.insert(2, sent(Waiter::Caller(recent), Duration::ZERO));
+        pending.insert(3, sent(Waiter::Caller(waiting), timeout * 2));
+        task.forget_abandoned(&mut pending);
+        assert_eq!(pending.keys().copied().collect::<Vec<_>>(), vec![2, 3]);
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::Cursor;
+
+use anyhow::anyhow;
+use bytes::{Buf, BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+use tracing::warn;
+
+use crate::HeosError;
+
+use super::{CommandPayload, Frame};
+
+/// Decodes the lines sent by a HEOS device into `Frame`s and encodes
+/// `CommandPayload`s as `heos://...\r\n` lines.
+///
+/// Works with any transport through `tokio_util::codec::Framed`:
+///
+/// ```no_run
+/// # async fn example() -> heos_daemon_rust::HeosResult<()> {
+/// use futures::SinkExt;
+/// use heos_daemon_rust::{CommandPayload, HeosCodec, SystemCommand};
+/// use tokio::net::TcpStream;
+/// use tokio_stream::StreamExt;
+/// use tokio_util::codec::Framed;
+///
+/// let stream = TcpStream::connect("192.168.178.34:1255").await?;
+/// let mut framed = Framed::new(stream, HeosCodec);
+/// framed.send(CommandPayload::from(SystemCommand::HeartBeat)).await?;
+/// let frame = framed.next().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeosCodec;
+
+impl Decoder for HeosCodec {
+    type Item = Frame;
+    type Error = HeosError;
+
+    // a line that can't be parsed is logged and skipped; returning an error
+    // would end a `Framed` stream for good.
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, HeosError> {
+        loop {
+            let mut buf = Cursor::new(&src[..]);
+            // wait for more data until there is a whole line.
+            if Frame::check(&mut buf).is_err() {
+                return Ok(None);
+            }
+            let len = buf.position() as usize;
+            buf.set_position(0);
+            let frame = Frame::parse(&mut buf);
+            if let Err(err) = &frame {
+                let line = String::from_utf8_lossy(&src[..len]);
+                warn!("skipping {:?}: {}", line.trim_end(), err);
+            }
+            src.advance(len);
+            if let Ok(frame) = frame {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, HeosError> {
+        match self.decode(src)? {
+            Some(frame) => Ok(Some(frame)),
+            None if src.is_empty() => Ok(None),
+            // the device closed the connection in the middle of a line.
+            None => Err(anyhow!("connection reset by peer").into()),
+        }
+    }
+}
+
+impl Encoder<CommandPayload> for HeosCodec {
+    type Error = HeosError;
+
+    fn encode(&mut self, command: CommandPayload, dst: &mut BytesMut) -> Result<(), HeosError> {
+        dst.put_slice(command.wire().as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HeosEvent, SystemCommand};
+    use futures::SinkExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+
+    #[tokio::test]
+    async fn frames_over_any_transport() {
+        let (client, mut device) = tokio::io::duplex(1024);
+        let mut framed = Framed::new(client, HeosCodec);
+        let heart_beat = CommandPayload::from(SystemCommand::HeartBeat);
+        framed.send(heart_beat).await.unwrap();
+        let mut buf = [0u8; 64];
+        let len = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"heos://system/heart_beat\r\n");
+
+        // a line may arrive in pieces.
+        device
+            .write_all(b"{\"heos\": {\"command\": \"system/heart_beat\", \"res")
+            .await
+            .unwrap();
+        device
+            .write_all(b"ult\": \"success\", \"message\": \"\"}}\r\n")
+            .await
+            .unwrap();
+        let frame = framed.next().await.unwrap().unwrap();
+        assert!(
+            matches!(frame, Frame::Response(response) if response.command_name == "system/heart_beat")
+        );
+    }
+
+    #[tokio::test]
+    async fn broken_lines_are_skipped() {
+        let (client, mut device) = tokio::io::duplex(1024);
+        let mut framed = Framed::new(client, HeosCodec);
+        device.write_all(b"not json\r\n").await.unwrap();
+        device
+            .write_all(
+                b"{\"heos\": {\"command\": \"event/players_changed\", \"message\": \"\"}}\r\n",
+            )
+            .await
+            .unwrap();
+        let frame = framed.next().await.unwrap().unwrap();
+        assert!(matches!(frame, Frame::Event(HeosEvent::PlayersChanged)));
+    }
+
+    #[tokio::test]
+    async fn closing_in_the_middle_of_a_line() {
+        let (client, mut device) = tokio::io::duplex(1024);
+        let mut framed = Framed::new(client, HeosCodec);
+        device.write_all(b"{\"heos\": ").await.unwrap();
+        drop(device);
+        assert!(framed.next().await.unwrap().is_err());
+        assert!(framed.next().await.is_none());
+    }
+}
@@ -1,26 +1,99 @@
-use crate::{Connection, Level, OnOrOff, PlayState, PlayerId};
-use std::fmt::{format, write, Display, Formatter};
-use tracing::enabled;
-
-const SYSTEM: &str = "system";
-const HEOS: &str = "heos";
+use crate::{
+    AddToQueueAid, ContainerId, GroupId, Level, MediaId, OnOrOff, PlayState, PlayerId, PresetId,
+    QueueId, QuickSelectId, Range, Repeat, SearchCriteriaId, Sequence, SourceId, Step,
+};
+use itertools::Itertools;
+use std::fmt::{Display, Formatter};
 
+#[derive(Clone)]
 pub struct CommandPayload(String);
+impl CommandPayload {
+    // any command as written in the cli documentation, with or without `heos://`.
+    pub fn raw(command: &str) -> CommandPayload {
+        CommandPayload(command.trim().trim_start_matches("heos://").to_owned())
+    }
+
+    // the command without its parameters, e.g. `player/get_volume`.
+    pub fn command_name(&self) -> &str {
+        self.0.split('?').next().unwrap_or_default()
+    }
+
+    /// The same command tagged with `SEQUENCE=<sequence>`, replacing any
+    /// sequence it had. The device echoes it in the answer.
+    pub fn with_sequence(&self, sequence: Sequence) -> CommandPayload {
+        let (name, params) = self.0.split_once('?').unwrap_or((&self.0, ""));
+        let sequence = format!("SEQUENCE={}", sequence);
+        let params = params
+            .split('&')
+            .filter(|param| !param.is_empty() && !param.starts_with("SEQUENCE="))
+            .chain(std::iter::once(sequence.as_str()))
+            .join("&");
+        CommandPayload(format!("{}?{}", name, params))
+    }
+
+    pub fn sequence(&self) -> Option<Sequence> {
+        let (_, params) = self.0.split_once('?')?;
+        params
+            .split('&')
+            .find_map(|param| param.strip_prefix("SEQUENCE="))
+            .and_then(|sequence| sequence.parse().ok())
+    }
+
+    // the line actually sent to the device. Unlike `Display` this includes
+    // the password of `system/sign_in`, so never log it.
+    pub(crate) fn wire(&self) -> String {
+        format!("heos://{}\r\n", self.0)
+    }
+}
+// shows the command with any password replaced by `***`.
 impl Display for CommandPayload {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "heos://{}", self.0)
+        let (name, params) = match self.0.split_once('?') {
+            Some((name, params)) => (name, params),
+            None => return write!(f, "heos://{}", self.0),
+        };
+        let params = params
+            .split('&')
+            .map(|param| match param.split_once('=') {
+                Some(("pw", _)) => "pw=***",
+                _ => param,
+            })
+            .join("&");
+        write!(f, "heos://{}?{}", name, params)
+    }
+}
+impl std::fmt::Debug for CommandPayload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CommandPayload({})", self)
     }
 }
 
 pub enum SystemCommand {
     RegisterForChangeEvents { enable: OnOrOff },
-    AccountCheck,
     SignIn { un: String, pw: String },
-    SignOut,
     HeartBeat,
     SpeakerReboot,
     PrettifyJson,
 }
+impl std::fmt::Debug for SystemCommand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SystemCommand::RegisterForChangeEvents { enable } => f
+                .debug_struct("RegisterForChangeEvents")
+                .field("enable", enable)
+                .finish(),
+            // never show the password.
+            SystemCommand::SignIn { un, .. } => f
+                .debug_struct("SignIn")
+                .field("un", un)
+                .field("pw", &"***")
+                .finish(),
+            SystemCommand::HeartBeat => write!(f, "HeartBeat"),
+            SystemCommand::SpeakerReboot => write!(f, "SpeakerReboot"),
+            SystemCommand::PrettifyJson => write!(f, "PrettifyJson"),
+        }
+    }
+}
 
 impl From<SystemCommand> for CommandPayload {
     fn from(cmd: SystemCommand) -> Self {
@@ -29,11 +102,11 @@ impl From<SystemCommand> for CommandPayload {
                 "system/register_for_change_events?enable={}",
                 enable
             )),
-            SystemCommand::AccountCheck => CommandPayload("system/account_check".to_owned()),
-            SystemCommand::SignIn { un, pw } => {
-                CommandPayload(format!("system/sign_in?un={}&pw={}", un, pw))
-            }
-            SystemCommand::SignOut => CommandPayload("system/sign_out".to_owned()),
+            SystemCommand::SignIn { un, pw } => CommandPayload(format!(
+                "system/sign_in?un={}&pw={}",
+                escape(&un),
+                escape(&pw)
+            )),
             SystemCommand::HeartBeat => CommandPayload("system/heart_beat".to_owned()),
             SystemCommand::SpeakerReboot => CommandPayload("system/speaker_reboot".to_owned()),
             SystemCommand::PrettifyJson => CommandPayload("system/prettify_json".to_owned()),
@@ -41,26 +114,112 @@ impl From<SystemCommand> for CommandPayload {
     }
 }
 pub enum PlayerCommand {
-    GetPlayers,
-    GetPlayerInfo { pid: PlayerId },
-    GetPlayState { pid: PlayerId },
-    SetPlayState { pid: PlayerId, state: PlayState },
-    GetNowPlayingMedia { pid: PlayerId },
-    GetPlayerVolume { pid: PlayerId },
-    SetPlayerVolume { pid: PlayerId, level: Level },
+    GetPlayerInfo {
+        pid: PlayerId,
+    },
+    GetPlayState {
+        pid: PlayerId,
+    },
+    SetPlayState {
+        pid: PlayerId,
+        state: PlayState,
+    },
+    GetNowPlayingMedia {
+        pid: PlayerId,
+    },
+    GetPlayerVolume {
+        pid: PlayerId,
+    },
+    SetPlayerVolume {
+        pid: PlayerId,
+        level: Level,
+    },
+    // step is 1..10, the device defaults to 5 if omitted.
+    VolumeUp {
+        pid: PlayerId,
+        step: Option<Step>,
+    },
+    VolumeDown {
+        pid: PlayerId,
+        step: Option<Step>,
+    },
+    GetMute {
+        pid: PlayerId,
+    },
+    SetMute {
+        pid: PlayerId,
+        state: OnOrOff,
+    },
+    ToggleMute {
+        pid: PlayerId,
+    },
+    GetPlayMode {
+        pid: PlayerId,
+    },
+    SetPlayMode {
+        pid: PlayerId,
+        repeat: Repeat,
+        shuffle: OnOrOff,
+    },
+    PlayNext {
+        pid: PlayerId,
+    },
+    PlayPrevious {
+        pid: PlayerId,
+    },
+    // quick selects are only supported by some models; id is 1..6.
+    GetQuickSelects {
+        pid: PlayerId,
+        id: Option<QuickSelectId>,
+    },
+    SetQuickSelect {
+        pid: PlayerId,
+        id: QuickSelectId,
+    },
+    PlayQuickSelect {
+        pid: PlayerId,
+        id: QuickSelectId,
+    },
+    CheckUpdate {
+        pid: PlayerId,
+    },
+    GetQueue {
+        pid: PlayerId,
+        range: Option<Range>,
+    },
+    PlayQueue {
+        pid: PlayerId,
+        qid: QueueId,
+    },
+    RemoveFromQueue {
+        pid: PlayerId,
+        qids: Vec<QueueId>,
+    },
+    SaveQueue {
+        pid: PlayerId,
+        name: String,
+    },
+    ClearQueue {
+        pid: PlayerId,
+    },
+    // moves the items `sqids` so they end up in front of `dqid`.
+    MoveQueueItem {
+        pid: PlayerId,
+        sqids: Vec<QueueId>,
+        dqid: QueueId,
+    },
 }
 impl From<PlayerCommand> for CommandPayload {
     fn from(command: PlayerCommand) -> Self {
         match command {
-            PlayerCommand::GetPlayers => CommandPayload("player/get_players".to_owned()),
             PlayerCommand::GetPlayerInfo { pid } => {
-                CommandPayload(format!("player/get_player?pid={}", pid))
+                CommandPayload(format!("player/get_player_info?pid={}", pid))
             }
             PlayerCommand::GetPlayState { pid } => {
                 CommandPayload(format!("player/get_play_state?pid={}", pid))
             }
             PlayerCommand::SetPlayState { pid, state } => {
-                CommandPayload(format!("player/get_play_state?pid={}&state={}", pid, state))
+                CommandPayload(format!("player/set_play_state?pid={}&state={}", pid, state))
             }
             PlayerCommand::GetNowPlayingMedia { pid } => {
                 CommandPayload(format!("player/get_now_playing_media?pid={}", pid))
@@ -69,8 +228,472 @@ impl From<PlayerCommand> for CommandPayload {
                 CommandPayload(format!("player/get_volume?pid={}", pid))
             }
             PlayerCommand::SetPlayerVolume { pid, level } => {
-                CommandPayload(format!("player/set_volume?pid={}&leve={}", pid, level))
+                CommandPayload(format!("player/set_volume?pid={}&level={}", pid, level))
+            }
+            PlayerCommand::VolumeUp { pid, step } => CommandPayload(format!(
+                "player/volume_up?pid={}{}",
+                pid,
+                optional_param("step", step)
+            )),
+            PlayerCommand::VolumeDown { pid, step } => CommandPayload(format!(
+                "player/volume_down?pid={}{}",
+                pid,
+                optional_param("step", step)
+            )),
+            PlayerCommand::GetMute { pid } => {
+                CommandPayload(format!("player/get_mute?pid={}", pid))
+            }
+            PlayerCommand::SetMute { pid, state } => {
+                CommandPayload(format!("player/set_mute?pid={}&state={}", pid, state))
+            }
+            PlayerCommand::ToggleMute { pid } => {
+                CommandPayload(format!("player/toggle_mute?pid={}", pid))
+            }
+            PlayerCommand::GetPlayMode { pid } => {
+                CommandPayload(format!("player/get_play_mode?pid={}", pid))
+            }
+            PlayerCommand::SetPlayMode {
+                pid,
+                repeat,
+                shuffle,
+            } => CommandPayload(format!(
+                "player/set_play_mode?pid={}&repeat={}&shuffle={}",
+                pid, repeat, shuffle
+            )),
+            PlayerCommand::PlayNext { pid } => {
+                CommandPayload(format!("player/play_next?pid={}", pid))
+            }
+            PlayerCommand::PlayPrevious { pid } => {
+                CommandPayload(format!("player/play_previous?pid={}", pid))
+            }
+            PlayerCommand::GetQuickSelects { pid, id } => CommandPayload(format!(
+                "player/get_quickselects?pid={}{}",
+                pid,
+                optional_param("id", id)
+            )),
+            PlayerCommand::SetQuickSelect { pid, id } => {
+                CommandPayload(format!("player/set_quickselect?pid={}&id={}", pid, id))
+            }
+            PlayerCommand::PlayQuickSelect { pid, id } => {
+                CommandPayload(format!("player/play_quickselect?pid={}&id={}", pid, id))
+            }
+            PlayerCommand::CheckUpdate { pid } => {
+                CommandPayload(format!("player/check_update?pid={}", pid))
+            }
+            PlayerCommand::GetQueue { pid, range } => CommandPayload(format!(
+                "player/get_queue?pid={}{}",
+                pid,
+                optional_param("range", range)
+            )),
+            PlayerCommand::PlayQueue { pid, qid } => {
+                CommandPayload(format!("player/play_queue?pid={}&qid={}", pid, qid))
+            }
+            PlayerCommand::RemoveFromQueue { pid, qids } => CommandPayload(format!(
+                "player/remove_from_queue?pid={}&qid={}",
+                pid,
+                qids.iter().join(",")
+            )),
+            PlayerCommand::SaveQueue { pid, name } => CommandPayload(format!(
+                "player/save_queue?pid={}&name={}",
+                pid,
+                escape(&name)
+            )),
+            PlayerCommand::ClearQueue { pid } => {
+                CommandPayload(format!("player/clear_queue?pid={}", pid))
+            }
+            PlayerCommand::MoveQueueItem { pid, sqids, dqid } => CommandPayload(format!(
+                "player/move_queue_item?pid={}&sqid={}&dqid={}",
+                pid,
+                sqids.iter().join(","),
+                dqid
+            )),
+        }
+    }
+}
+
+/// Escapes a string argument the way the HEOS CLI expects it: only `&`,
+/// `=` and `%` are special and become `%26`, `%3D` and `%25`.
+pub fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("%26"),
+            '=' => escaped.push_str("%3D"),
+            '%' => escaped.push_str("%25"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// renders `&name=value` for optional parameters, or nothing at all.
+fn optional_param<T: Display>(name: &str, value: Option<T>) -> String {
+    match value {
+        Some(value) => format!("&{}={}", name, value),
+        None => String::new(),
+    }
+}
+
+pub enum GroupCommand {
+    GetGroupInfo {
+        gid: GroupId,
+    },
+    // creates, modifies or (with no members) ungroups the group led by `leader`.
+    SetGroup {
+        leader: PlayerId,
+        members: Vec<PlayerId>,
+    },
+    GetGroupVolume {
+        gid: GroupId,
+    },
+    SetGroupVolume {
+        gid: GroupId,
+        level: Level,
+    },
+    VolumeUp {
+        gid: GroupId,
+        step: Option<Step>,
+    },
+    VolumeDown {
+        gid: GroupId,
+        step: Option<Step>,
+    },
+    GetMute {
+        gid: GroupId,
+    },
+    SetMute {
+        gid: GroupId,
+        state: OnOrOff,
+    },
+    ToggleMute {
+        gid: GroupId,
+    },
+}
+impl From<GroupCommand> for CommandPayload {
+    fn from(command: GroupCommand) -> Self {
+        match command {
+            GroupCommand::GetGroupInfo { gid } => {
+                CommandPayload(format!("group/get_group_info?gid={}", gid))
+            }
+            GroupCommand::SetGroup { leader, members } => {
+                let pids = std::iter::once(leader)
+                    .chain(members.into_iter().filter(|pid| *pid != leader))
+                    .join(",");
+                CommandPayload(format!("group/set_group?pid={}", pids))
+            }
+            GroupCommand::GetGroupVolume { gid } => {
+                CommandPayload(format!("group/get_volume?gid={}", gid))
+            }
+            GroupCommand::SetGroupVolume { gid, level } => {
+                CommandPayload(format!("group/set_volume?gid={}&level={}", gid, level))
+            }
+            GroupCommand::VolumeUp { gid, step } => CommandPayload(format!(
+                "group/volume_up?gid={}{}",
+                gid,
+                optional_param("step", step)
+            )),
+            GroupCommand::VolumeDown { gid, step } => CommandPayload(format!(
+                "group/volume_down?gid={}{}",
+                gid,
+                optional_param("step", step)
+            )),
+            GroupCommand::GetMute { gid } => CommandPayload(format!("group/get_mute?gid={}", gid)),
+            GroupCommand::SetMute { gid, state } => {
+                CommandPayload(format!("group/set_mute?gid={}&state={}", gid, state))
+            }
+            GroupCommand::ToggleMute { gid } => {
+                CommandPayload(format!("group/toggle_mute?gid={}", gid))
+            }
+        }
+    }
+}
+
+pub enum BrowseCommand {
+    GetMusicSources,
+    GetSourceInfo {
+        sid: SourceId,
+    },
+    // browses the top level of a source if no container is given.
+    Browse {
+        sid: SourceId,
+        cid: Option<ContainerId>,
+        range: Option<Range>,
+    },
+    GetSearchCriteria {
+        sid: SourceId,
+    },
+    Search {
+        sid: SourceId,
+        search: String,
+        scid: SearchCriteriaId,
+        range: Option<Range>,
+    },
+    // plays a station; cid is needed for stations found by browsing a container.
+    PlayStream {
+        pid: PlayerId,
+        sid: SourceId,
+        cid: Option<ContainerId>,
+        mid: MediaId,
+        name: String,
+    },
+    PlayPreset {
+        pid: PlayerId,
+        preset: PresetId,
+    },
+    // plays an input of this player, or of the player `spid` if given.
+    PlayInput {
+        pid: PlayerId,
+        spid: Option<PlayerId>,
+        input: String,
+    },
+    PlayUrl {
+        pid: PlayerId,
+        url: String,
+    },
+    // adds a whole container, or a single track of it if mid is given.
+    AddToQueue {
+        pid: PlayerId,
+        sid: SourceId,
+        cid: ContainerId,
+        mid: Option<MediaId>,
+        aid: AddToQueueAid,
+    },
+    RenamePlaylist {
+        sid: SourceId,
+        cid: ContainerId,
+        name: String,
+    },
+    DeletePlaylist {
+        sid: SourceId,
+        cid: ContainerId,
+    },
+    RetrieveMetadata {
+        sid: SourceId,
+        cid: ContainerId,
+    },
+    GetServiceOptions {
+        sid: SourceId,
+    },
+    SetServiceOption {
+        option: ServiceOptionAction,
+    },
+}
+
+// the options of `browse/set_service_option`, each with the parameters it needs.
+pub enum ServiceOptionAction {
+    AddTrackToLibrary {
+        sid: SourceId,
+        mid: MediaId,
+    },
+    AddAlbumToLibrary {
+        sid: SourceId,
+        cid: ContainerId,
+    },
+    AddStationToLibrary {
+        sid: SourceId,
+        mid: MediaId,
+    },
+    AddPlaylistToLibrary {
+        sid: SourceId,
+        cid: ContainerId,
+        name: String,
+    },
+    RemoveTrackFromLibrary {
+        sid: SourceId,
+        mid: MediaId,
+    },
+    RemoveAlbumFromLibrary {
+        sid: SourceId,
+        cid: ContainerId,
+    },
+    RemoveStationFromLibrary {
+        sid: SourceId,
+        mid: MediaId,
+    },
+    RemovePlaylistFromLibrary {
+        sid: SourceId,
+        cid: ContainerId,
+    },
+    ThumbsUp {
+        sid: SourceId,
+        pid: PlayerId,
+    },
+    ThumbsDown {
+        sid: SourceId,
+        pid: PlayerId,
+    },
+    CreateNewStation {
+        sid: SourceId,
+        name: String,
+        range: Option<Range>,
+    },
+    AddToFavoritesFromNowPlaying {
+        pid: PlayerId,
+    },
+    AddToFavorites {
+        sid: SourceId,
+        mid: MediaId,
+        name: String,
+    },
+    RemoveFromFavorites {
+        mid: MediaId,
+    },
+}
+
+// the heos favorites are always source 1028.
+const FAVORITES_SID: SourceId = 1028;
+
+impl From<BrowseCommand> for CommandPayload {
+    fn from(command: BrowseCommand) -> Self {
+        match command {
+            BrowseCommand::GetMusicSources => CommandPayload("browse/get_music_sources".to_owned()),
+            BrowseCommand::GetSourceInfo { sid } => {
+                CommandPayload(format!("browse/get_source_info?sid={}", sid))
+            }
+            BrowseCommand::Browse { sid, cid, range } => CommandPayload(format!(
+                "browse/browse?sid={}{}{}",
+                sid,
+                optional_param("cid", cid.as_deref().map(escape)),
+                optional_param("range", range)
+            )),
+            BrowseCommand::GetSearchCriteria { sid } => {
+                CommandPayload(format!("browse/get_search_criteria?sid={}", sid))
+            }
+            BrowseCommand::Search {
+                sid,
+                search,
+                scid,
+                range,
+            } => CommandPayload(format!(
+                "browse/search?sid={}&search={}&scid={}{}",
+                sid,
+                escape(&search),
+                scid,
+                optional_param("range", range)
+            )),
+            BrowseCommand::PlayStream {
+                pid,
+                sid,
+                cid,
+                mid,
+                name,
+            } => CommandPayload(format!(
+                "browse/play_stream?pid={}&sid={}{}&mid={}&name={}",
+                pid,
+                sid,
+                optional_param("cid", cid.as_deref().map(escape)),
+                escape(&mid),
+                escape(&name)
+            )),
+            BrowseCommand::PlayPreset { pid, preset } => {
+                CommandPayload(format!("browse/play_preset?pid={}&preset={}", pid, preset))
             }
+            BrowseCommand::PlayInput { pid, spid, input } => CommandPayload(format!(
+                "browse/play_input?pid={}{}&input={}",
+                pid,
+                optional_param("spid", spid),
+                escape(&input)
+            )),
+            BrowseCommand::PlayUrl { pid, url } => CommandPayload(format!(
+                "browse/play_stream?pid={}&url={}",
+                pid,
+                escape(&url)
+            )),
+            BrowseCommand::AddToQueue {
+                pid,
+                sid,
+                cid,
+                mid,
+                aid,
+            } => CommandPayload(format!(
+                "browse/add_to_queue?pid={}&sid={}&cid={}{}&aid={}",
+                pid,
+                sid,
+                escape(&cid),
+                optional_param("mid", mid.as_deref().map(escape)),
+                aid
+            )),
+            BrowseCommand::RenamePlaylist { sid, cid, name } => CommandPayload(format!(
+                "browse/rename_playlist?sid={}&cid={}&name={}",
+                sid,
+                escape(&cid),
+                escape(&name)
+            )),
+            BrowseCommand::DeletePlaylist { sid, cid } => CommandPayload(format!(
+                "browse/delete_playlist?sid={}&cid={}",
+                sid,
+                escape(&cid)
+            )),
+            BrowseCommand::RetrieveMetadata { sid, cid } => CommandPayload(format!(
+                "browse/retrieve_metadata?sid={}&cid={}",
+                sid,
+                escape(&cid)
+            )),
+            BrowseCommand::GetServiceOptions { sid } => {
+                CommandPayload(format!("browse/get_service_options?sid={}", sid))
+            }
+            BrowseCommand::SetServiceOption { option } => CommandPayload(format!(
+                "browse/set_service_option?{}",
+                service_option_params(option)
+            )),
+        }
+    }
+}
+
+fn service_option_params(option: ServiceOptionAction) -> String {
+    match option {
+        ServiceOptionAction::AddTrackToLibrary { sid, mid } => {
+            format!("sid={}&option=1&mid={}", sid, escape(&mid))
+        }
+        ServiceOptionAction::AddAlbumToLibrary { sid, cid } => {
+            format!("sid={}&option=2&cid={}", sid, escape(&cid))
+        }
+        ServiceOptionAction::AddStationToLibrary { sid, mid } => {
+            format!("sid={}&option=3&mid={}", sid, escape(&mid))
+        }
+        ServiceOptionAction::AddPlaylistToLibrary { sid, cid, name } => {
+            format!(
+                "sid={}&option=4&cid={}&name={}",
+                sid,
+                escape(&cid),
+                escape(&name)
+            )
+        }
+        ServiceOptionAction::RemoveTrackFromLibrary { sid, mid } => {
+            format!("sid={}&option=5&mid={}", sid, escape(&mid))
+        }
+        ServiceOptionAction::RemoveAlbumFromLibrary { sid, cid } => {
+            format!("sid={}&option=6&cid={}", sid, escape(&cid))
+        }
+        ServiceOptionAction::RemoveStationFromLibrary { sid, mid } => {
+            format!("sid={}&option=7&mid={}", sid, escape(&mid))
+        }
+        ServiceOptionAction::RemovePlaylistFromLibrary { sid, cid } => {
+            format!("sid={}&option=8&cid={}", sid, escape(&cid))
+        }
+        ServiceOptionAction::ThumbsUp { sid, pid } => format!("sid={}&option=11&pid={}", sid, pid),
+        ServiceOptionAction::ThumbsDown { sid, pid } => {
+            format!("sid={}&option=12&pid={}", sid, pid)
+        }
+        ServiceOptionAction::CreateNewStation { sid, name, range } => format!(
+            "sid={}&option=13&name={}{}",
+            sid,
+            escape(&name),
+            optional_param("range", range)
+        ),
+        ServiceOptionAction::AddToFavoritesFromNowPlaying { pid } => {
+            format!("option=19&pid={}", pid)
+        }
+        ServiceOptionAction::AddToFavorites { sid, mid, name } => {
+            format!(
+                "sid={}&option=19&mid={}&name={}",
+                sid,
+                escape(&mid),
+                escape(&name)
+            )
+        }
+        ServiceOptionAction::RemoveFromFavorites { mid } => {
+            format!("sid={}&option=20&mid={}", FAVORITES_SID, escape(&mid))
         }
     }
 }
@@ -78,6 +701,8 @@ impl From<PlayerCommand> for CommandPayload {
 pub enum HeosCommand {
     System(SystemCommand),
     Player(PlayerCommand),
+    Group(GroupCommand),
+    Browse(BrowseCommand),
 }
 
 impl From<HeosCommand> for CommandPayload {
@@ -85,6 +710,571 @@ impl From<HeosCommand> for CommandPayload {
         match cmd {
             HeosCommand::System(cmd) => cmd.into(),
             HeosCommand::Player(cmd) => cmd.into(),
+            HeosCommand::Group(cmd) => cmd.into(),
+            HeosCommand::Browse(cmd) => cmd.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the line written to the device, without `\r\n`.
+    fn wire<T: Into<CommandPayload>>(command: T) -> String {
+        let line = command.into().wire();
+        line.strip_suffix("\r\n").unwrap().to_owned()
+    }
+
+    #[test]
+    fn player_commands() {
+        let range = Range { start: 0, end: 9 };
+        let cases = vec![
+            (
+                PlayerCommand::GetPlayerInfo { pid: 1 },
+                "heos://player/get_player_info?pid=1",
+            ),
+            (
+                PlayerCommand::GetPlayState { pid: 1 },
+                "heos://player/get_play_state?pid=1",
+            ),
+            (
+                PlayerCommand::SetPlayState {
+                    pid: 1,
+                    state: PlayState::Pause,
+                },
+                "heos://player/set_play_state?pid=1&state=pause",
+            ),
+            (
+                PlayerCommand::GetNowPlayingMedia { pid: 1 },
+                "heos://player/get_now_playing_media?pid=1",
+            ),
+            (
+                PlayerCommand::GetPlayerVolume { pid: 1 },
+                "heos://player/get_volume?pid=1",
+            ),
+            (
+                PlayerCommand::SetPlayerVolume { pid: 1, level: 42 },
+                "heos://player/set_volume?pid=1&level=42",
+            ),
+            (
+                PlayerCommand::VolumeUp { pid: 1, step: None },
+                "heos://player/volume_up?pid=1",
+            ),
+            (
+                PlayerCommand::VolumeUp {
+                    pid: 1,
+                    step: Some(5),
+                },
+                "heos://player/volume_up?pid=1&step=5",
+            ),
+            (
+                PlayerCommand::VolumeDown { pid: 1, step: None },
+                "heos://player/volume_down?pid=1",
+            ),
+            (
+                PlayerCommand::VolumeDown {
+                    pid: 1,
+                    step: Some(3),
+                },
+                "heos://player/volume_down?pid=1&step=3",
+            ),
+            (
+                PlayerCommand::GetMute { pid: 1 },
+                "heos://player/get_mute?pid=1",
+            ),
+            (
+                PlayerCommand::SetMute {
+                    pid: 1,
+                    state: OnOrOff::On,
+                },
+                "heos://player/set_mute?pid=1&state=on",
+            ),
+            (
+                PlayerCommand::ToggleMute { pid: 1 },
+                "heos://player/toggle_mute?pid=1",
+            ),
+            (
+                PlayerCommand::GetPlayMode { pid: 1 },
+                "heos://player/get_play_mode?pid=1",
+            ),
+            (
+                PlayerCommand::SetPlayMode {
+                    pid: 1,
+                    repeat: Repeat::OnAll,
+                    shuffle: OnOrOff::Off,
+                },
+                "heos://player/set_play_mode?pid=1&repeat=on_all&shuffle=off",
+            ),
+            (
+                PlayerCommand::PlayNext { pid: 1 },
+                "heos://player/play_next?pid=1",
+            ),
+            (
+                PlayerCommand::PlayPrevious { pid: 1 },
+                "heos://player/play_previous?pid=1",
+            ),
+            (
+                PlayerCommand::GetQuickSelects { pid: 1, id: None },
+                "heos://player/get_quickselects?pid=1",
+            ),
+            (
+                PlayerCommand::GetQuickSelects {
+                    pid: 1,
+                    id: Some(2),
+                },
+                "heos://player/get_quickselects?pid=1&id=2",
+            ),
+            (
+                PlayerCommand::SetQuickSelect { pid: 1, id: 2 },
+                "heos://player/set_quickselect?pid=1&id=2",
+            ),
+            (
+                PlayerCommand::PlayQuickSelect { pid: 1, id: 2 },
+                "heos://player/play_quickselect?pid=1&id=2",
+            ),
+            (
+                PlayerCommand::CheckUpdate { pid: 1 },
+                "heos://player/check_update?pid=1",
+            ),
+            (
+                PlayerCommand::GetQueue {
+                    pid: 1,
+                    range: None,
+                },
+                "heos://player/get_queue?pid=1",
+            ),
+            (
+                PlayerCommand::GetQueue {
+                    pid: 1,
+                    range: Some(range),
+                },
+                "heos://player/get_queue?pid=1&range=0,9",
+            ),
+            (
+                PlayerCommand::PlayQueue { pid: 1, qid: 7 },
+                "heos://player/play_queue?pid=1&qid=7",
+            ),
+            (
+                PlayerCommand::RemoveFromQueue {
+                    pid: 1,
+                    qids: vec![2, 4, 6],
+                },
+                "heos://player/remove_from_queue?pid=1&qid=2,4,6",
+            ),
+            (
+                PlayerCommand::SaveQueue {
+                    pid: 1,
+                    name: "Sunday".to_owned(),
+                },
+                "heos://player/save_queue?pid=1&name=Sunday",
+            ),
+            (
+                PlayerCommand::ClearQueue { pid: 1 },
+                "heos://player/clear_queue?pid=1",
+            ),
+            (
+                PlayerCommand::MoveQueueItem {
+                    pid: 1,
+                    sqids: vec![2, 3],
+                    dqid: 1,
+                },
+                "heos://player/move_queue_item?pid=1&sqid=2,3&dqid=1",
+            ),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(wire(command), expected);
+        }
+    }
+
+    #[test]
+    fn group_commands() {
+        let cases = vec![
+            (
+                GroupCommand::GetGroupInfo { gid: -5 },
+                "heos://group/get_group_info?gid=-5",
+            ),
+            (
+                GroupCommand::SetGroup {
+                    leader: 1,
+                    members: vec![2, 3],
+                },
+                "heos://group/set_group?pid=1,2,3",
+            ),
+            // the leader is only sent once, even if it's among the members.
+            (
+                GroupCommand::SetGroup {
+                    leader: 1,
+                    members: vec![2, 1, 3],
+                },
+                "heos://group/set_group?pid=1,2,3",
+            ),
+            (
+                GroupCommand::SetGroup {
+                    leader: 1,
+                    members: vec![],
+                },
+                "heos://group/set_group?pid=1",
+            ),
+            (
+                GroupCommand::GetGroupVolume { gid: -5 },
+                "heos://group/get_volume?gid=-5",
+            ),
+            (
+                GroupCommand::SetGroupVolume { gid: -5, level: 30 },
+                "heos://group/set_volume?gid=-5&level=30",
+            ),
+            (
+                GroupCommand::VolumeUp {
+                    gid: -5,
+                    step: None,
+                },
+                "heos://group/volume_up?gid=-5",
+            ),
+            (
+                GroupCommand::VolumeDown {
+                    gid: -5,
+                    step: Some(3),
+                },
+                "heos://group/volume_down?gid=-5&step=3",
+            ),
+            (
+                GroupCommand::GetMute { gid: -5 },
+                "heos://group/get_mute?gid=-5",
+            ),
+            (
+                GroupCommand::SetMute {
+                    gid: -5,
+                    state: OnOrOff::On,
+                },
+                "heos://group/set_mute?gid=-5&state=on",
+            ),
+            (
+                GroupCommand::ToggleMute { gid: -5 },
+                "heos://group/toggle_mute?gid=-5",
+            ),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(wire(command), expected);
+        }
+    }
+
+    #[test]
+    fn browse_commands() {
+        let range = Range { start: 0, end: 49 };
+        let cases = vec![
+            (
+                BrowseCommand::GetMusicSources,
+                "heos://browse/get_music_sources",
+            ),
+            (
+                BrowseCommand::GetSourceInfo { sid: 10 },
+                "heos://browse/get_source_info?sid=10",
+            ),
+            (
+                BrowseCommand::Browse {
+                    sid: 10,
+                    cid: None,
+                    range: None,
+                },
+                "heos://browse/browse?sid=10",
+            ),
+            (
+                BrowseCommand::Browse {
+                    sid: 10,
+                    cid: Some("album:1".to_owned()),
+                    range: Some(range),
+                },
+                "heos://browse/browse?sid=10&cid=album:1&range=0,49",
+            ),
+            (
+                BrowseCommand::GetSearchCriteria { sid: 10 },
+                "heos://browse/get_search_criteria?sid=10",
+            ),
+            (
+                BrowseCommand::Search {
+                    sid: 10,
+                    search: "Queen".to_owned(),
+                    scid: 1,
+                    range: None,
+                },
+                "heos://browse/search?sid=10&search=Queen&scid=1",
+            ),
+            (
+                BrowseCommand::PlayStream {
+                    pid: 1,
+                    sid: 3,
+                    cid: None,
+                    mid: "s1234".to_owned(),
+                    name: "Radio".to_owned(),
+                },
+                "heos://browse/play_stream?pid=1&sid=3&mid=s1234&name=Radio",
+            ),
+            (
+                BrowseCommand::PlayStream {
+                    pid: 1,
+                    sid: 3,
+                    cid: Some("c1".to_owned()),
+                    mid: "s1234".to_owned(),
+                    name: "Radio".to_owned(),
+                },
+                "heos://browse/play_stream?pid=1&sid=3&cid=c1&mid=s1234&name=Radio",
+            ),
+            (
+                BrowseCommand::PlayPreset { pid: 1, preset: 2 },
+                "heos://browse/play_preset?pid=1&preset=2",
+            ),
+            (
+                BrowseCommand::PlayInput {
+                    pid: 1,
+                    spid: None,
+                    input: "inputs/aux_in_1".to_owned(),
+                },
+                "heos://browse/play_input?pid=1&input=inputs/aux_in_1",
+            ),
+            (
+                BrowseCommand::PlayInput {
+                    pid: 1,
+                    spid: Some(2),
+                    input: "inputs/aux_in_1".to_owned(),
+                },
+                "heos://browse/play_input?pid=1&spid=2&input=inputs/aux_in_1",
+            ),
+            (
+                BrowseCommand::PlayUrl {
+                    pid: 1,
+                    url: "http://example.com/stream?a=1&b=2".to_owned(),
+                },
+                "heos://browse/play_stream?pid=1&url=http://example.com/stream?a%3D1%26b%3D2",
+            ),
+            (
+                BrowseCommand::AddToQueue {
+                    pid: 1,
+                    sid: 10,
+                    cid: "album:1".to_owned(),
+                    mid: None,
+                    aid: AddToQueueAid::AddToEnd,
+                },
+                "heos://browse/add_to_queue?pid=1&sid=10&cid=album:1&aid=3",
+            ),
+            (
+                BrowseCommand::AddToQueue {
+                    pid: 1,
+                    sid: 10,
+                    cid: "album:1".to_owned(),
+                    mid: Some("track:2".to_owned()),
+                    aid: AddToQueueAid::PlayNow,
+                },
+                "heos://browse/add_to_queue?pid=1&sid=10&cid=album:1&mid=track:2&aid=1",
+            ),
+            (
+                BrowseCommand::RenamePlaylist {
+                    sid: 1025,
+                    cid: "p1".to_owned(),
+                    name: "Mix".to_owned(),
+                },
+                "heos://browse/rename_playlist?sid=1025&cid=p1&name=Mix",
+            ),
+            (
+                BrowseCommand::DeletePlaylist {
+                    sid: 1025,
+                    cid: "p1".to_owned(),
+                },
+                "heos://browse/delete_playlist?sid=1025&cid=p1",
+            ),
+            (
+                BrowseCommand::RetrieveMetadata {
+                    sid: 10,
+                    cid: "album:1".to_owned(),
+                },
+                "heos://browse/retrieve_metadata?sid=10&cid=album:1",
+            ),
+            (
+                BrowseCommand::GetServiceOptions { sid: 10 },
+                "heos://browse/get_service_options?sid=10",
+            ),
+        ];
+        for (command, expected) in cases {
+            assert_eq!(wire(command), expected);
+        }
+    }
+
+    #[test]
+    fn service_options() {
+        let mid = || "m1".to_owned();
+        let cid = || "c1".to_owned();
+        let cases = vec![
+            (
+                ServiceOptionAction::AddTrackToLibrary {
+                    sid: 10,
+                    mid: mid(),
+                },
+                "sid=10&option=1&mid=m1",
+            ),
+            (
+                ServiceOptionAction::AddAlbumToLibrary {
+                    sid: 10,
+                    cid: cid(),
+                },
+                "sid=10&option=2&cid=c1",
+            ),
+            (
+                ServiceOptionAction::AddStationToLibrary {
+                    sid: 10,
+                    mid: mid(),
+                },
+                "sid=10&option=3&mid=m1",
+            ),
+            (
+                ServiceOptionAction::AddPlaylistToLibrary {
+                    sid: 10,
+                    cid: cid(),
+                    name: "Mix & Match".to_owned(),
+                },
+                "sid=10&option=4&cid=c1&name=Mix %26 Match",
+            ),
+            (
+                ServiceOptionAction::RemoveTrackFromLibrary {
+                    sid: 10,
+                    mid: mid(),
+                },
+                "sid=10&option=5&mid=m1",
+            ),
+            (
+                ServiceOptionAction::RemoveAlbumFromLibrary {
+                    sid: 10,
+                    cid: cid(),
+                },
+                "sid=10&option=6&cid=c1",
+            ),
+            (
+                ServiceOptionAction::RemoveStationFromLibrary {
+                    sid: 10,
+                    mid: mid(),
+                },
+                "sid=10&option=7&mid=m1",
+            ),
+            (
+                ServiceOptionAction::RemovePlaylistFromLibrary {
+                    sid: 10,
+                    cid: cid(),
+                },
+                "sid=10&option=8&cid=c1",
+            ),
+            (
+                ServiceOptionAction::ThumbsUp { sid: 3, pid: 1 },
+                "sid=3&option=11&pid=1",
+            ),
+            (
+                ServiceOptionAction::ThumbsDown { sid: 3, pid: 1 },
+                "sid=3&option=12&pid=1",
+            ),
+            (
+                ServiceOptionAction::CreateNewStation {
+                    sid: 3,
+                    name: "Queen".to_owned(),
+                    range: None,
+                },
+                "sid=3&option=13&name=Queen",
+            ),
+            (
+                ServiceOptionAction::CreateNewStation {
+                    sid: 3,
+                    name: "Queen".to_owned(),
+                    range: Some(Range { start: 0, end: 9 }),
+                },
+                "sid=3&option=13&name=Queen&range=0,9",
+            ),
+            (
+                ServiceOptionAction::AddToFavoritesFromNowPlaying { pid: 1 },
+                "option=19&pid=1",
+            ),
+            (
+                ServiceOptionAction::AddToFavorites {
+                    sid: 3,
+                    mid: mid(),
+                    name: "Radio".to_owned(),
+                },
+                "sid=3&option=19&mid=m1&name=Radio",
+            ),
+            // favorites are removed from their own source.
+            (
+                ServiceOptionAction::RemoveFromFavorites { mid: mid() },
+                "sid=1028&option=20&mid=m1",
+            ),
+        ];
+        for (option, expected) in cases {
+            let command = BrowseCommand::SetServiceOption { option };
+            let expected = format!("heos://browse/set_service_option?{}", expected);
+            assert_eq!(wire(command), expected);
+        }
+    }
+
+    #[test]
+    fn string_arguments_are_escaped() {
+        let sign_in = SystemCommand::SignIn {
+            un: "me&you@example.com".to_owned(),
+            pw: "100%=sure".to_owned(),
+        };
+        assert_eq!(
+            wire(sign_in),
+            "heos://system/sign_in?un=me%26you@example.com&pw=100%25%3Dsure"
+        );
+        let search = BrowseCommand::Search {
+            sid: 10,
+            search: "Simon & Garfunkel".to_owned(),
+            scid: 1,
+            range: Some(Range { start: 0, end: 49 }),
+        };
+        assert_eq!(
+            wire(search),
+            "heos://browse/search?sid=10&search=Simon %26 Garfunkel&scid=1&range=0,49"
+        );
+        let save_queue = PlayerCommand::SaveQueue {
+            pid: 1,
+            name: "a=b & 50% off".to_owned(),
+        };
+        assert_eq!(
+            wire(save_queue),
+            "heos://player/save_queue?pid=1&name=a%3Db %26 50%25 off"
+        );
+    }
+
+    #[test]
+    fn passwords_are_not_shown() {
+        let sign_in = || SystemCommand::SignIn {
+            un: "me@example.com".to_owned(),
+            pw: "s3cret".to_owned(),
+        };
+        let debug = format!("{:?}", sign_in());
+        assert_eq!(debug, r#"SignIn { un: "me@example.com", pw: "***" }"#);
+        let payload = CommandPayload::from(sign_in());
+        let display = format!("{}", payload);
+        assert_eq!(display, "heos://system/sign_in?un=me@example.com&pw=***");
+        let debug = format!("{:?}", payload);
+        assert_eq!(
+            debug,
+            "CommandPayload(heos://system/sign_in?un=me@example.com&pw=***)"
+        );
+        // only the wire format has it.
+        assert!(payload.wire().contains("pw=s3cret"));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn escaping_round_trips(value in ".*") {
+            let escaped = escape(&value);
+            proptest::prop_assert!(!escaped.contains('&') && !escaped.contains('='));
+            proptest::prop_assert_eq!(crate::unescape(&escaped), value);
+        }
+
+        #[test]
+        fn playlist_names_survive_the_command_line(name in "[ -~]{0,40}") {
+            // search terms and playlist names are mostly printable ascii.
+            let command = wire(PlayerCommand::SaveQueue { pid: 1, name: name.clone() });
+            let (_, argument) = command.split_once("&name=").unwrap();
+            proptest::prop_assert!(!argument.contains('&'));
+            proptest::prop_assert_eq!(crate::unescape(argument), name);
         }
     }
 }
@@ -2,11 +2,11 @@ use anyhow::anyhow;
 use anyhow::Context;
 use std::io::Cursor;
 
-use crate::{CommandResponse, EventResponse};
+use crate::{CommandResponse, HeosEvent};
 use bytes::Buf;
 use serde_json::Value as Json;
 
-use crate::error::{ErrorMessage, HeosError, HeosErrorCode};
+use crate::error::{ErrorMessage, HeosError};
 
 use super::response_line::*;
 
@@ -14,7 +14,7 @@ use super::response_line::*;
 pub enum Frame {
     UnderProcess(String),
     Response(CommandResponse),
-    Event(EventResponse),
+    Event(HeosEvent),
     Error(ErrorMessage),
 }
 
@@ -117,21 +117,21 @@ mod parsers {
         ) {
             (cmd, Some(HeosResultState::Failure), message) => {
                 let mut error: ErrorMessage = {
-                    let json = qs_to_json(&message);
+                    let json = qs_to_json(message);
                     serde_json::from_value(json)
                         .context(format!("could not parse {} as json", &message))?
                 };
                 error.context = Some(cmd.name());
+                error.sequence = sequence(message);
                 Ok(Frame::Error(error))
             }
             (ResponseName::EventName(name), _, message) => {
-                let json = qs_to_json(&message);
-                Ok(Frame::Event(EventResponse {
-                    event_name: name.clone(),
-                    message: json,
-                }))
+                let json = qs_to_json(message);
+                Ok(Frame::Event(HeosEvent::parse(name, json)))
             },
-            (ResponseName::CommandName(name), _, message) if message == "command under process" => {
+            (ResponseName::CommandName(name), _, message)
+                if message.starts_with("command under process") =>
+            {
                 Ok(Frame::UnderProcess(name.clone()))
             }
             (ResponseName::CommandName(name), _, message) => {
@@ -141,6 +141,7 @@ mod parsers {
                     message: parsed_message,
                     payload: response.payload,
                     options: response.options,
+                    sequence: sequence(message),
                 }))
             }
         }
@@ -156,8 +157,8 @@ mod parsers {
             S: Serializer,
         {
             match name {
-                ResponseName::CommandName(s) => serializer.serialize_str(&s),
-                ResponseName::EventName(s) => serializer.serialize_str(&s),
+                ResponseName::CommandName(s) => serializer.serialize_str(s),
+                ResponseName::EventName(s) => serializer.serialize_str(s),
             }
         }
 
@@ -1,30 +1,35 @@
 use anyhow::{anyhow, Context};
-use std::fmt::{Display, Write};
-use std::io::Cursor;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
 
-use crate::{CommandResponse, HeosError, HeosResult};
-use async_stream::try_stream;
-use async_trait::async_trait;
-use bytes::{Buf, BytesMut};
+use crate::{discovery, CommandResponse, HeosError, HeosResult, Sequence};
+use futures::SinkExt;
 use log::trace;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
 use tokio::net::{TcpStream, ToSocketAddrs};
-use tokio_stream::Stream;
-use tracing::{debug, info, warn};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+use tracing::{debug, info};
 
+mod client;
+mod codec;
 mod command;
 mod frame;
+mod request;
 mod response_line;
+pub use client::*;
+pub use codec::*;
 pub use command::*;
 pub use frame::*;
+pub use request::*;
+pub use response_line::unescape;
 
-// copied pasted from https://docs.rs/crate/mini-redis/0.4.1/source/src/connection.rs
 #[derive(Debug)]
 pub struct Connection {
-    stream: BufWriter<TcpStream>,
+    framed: Framed<TcpStream, HeosCodec>,
 
-    // The buffer for reading frames.
-    buffer: BytesMut,
+    // the sequence of the last command sent.
+    sequence: Sequence,
 }
 
 impl Connection {
@@ -34,23 +39,89 @@ impl Connection {
         Ok(Self::new(stream))
     }
 
+    /// Connects to the first HEOS device answering an SSDP search within
+    /// `timeout`, or fails with `HeosError::NoDevicesFound`.
+    pub async fn discover(timeout: Duration) -> HeosResult<Connection> {
+        let device = discovery::discover(timeout).await?;
+        info!(
+            "discovered {} at {}",
+            device.friendly_name.as_deref().unwrap_or("heos device"),
+            device.address
+        );
+        Self::connect(device.heos_address()).await
+    }
+
     pub fn new(socket: TcpStream) -> Connection {
         Connection {
-            stream: BufWriter::new(socket),
-            // Default to a 4KB read buffer.
-            buffer: BytesMut::with_capacity(16 * 1024),
+            framed: Framed::new(socket, HeosCodec),
+            sequence: 0,
         }
     }
+    pub fn peer_addr(&self) -> HeosResult<SocketAddr> {
+        Ok(self.framed.get_ref().peer_addr()?)
+    }
     pub async fn try_clone(&mut self) -> crate::HeosResult<Self> {
-        let addr = self.stream.get_ref().peer_addr()?;
+        let addr = self.peer_addr()?;
         let stream = TcpStream::connect(addr).await?;
         Ok(Connection::new(stream))
     }
-    pub async fn execute_command<T: Into<CommandPayload>>(
+    /// Executes a command and parses its answer into the command's response type.
+    pub async fn execute_command<R: HeosRequest>(&mut self, command: R) -> HeosResult<R::Response> {
+        let response = self.execute_raw(command).await?;
+        R::parse_response(response)
+    }
+
+    /// Like `execute_command` but fails with `HeosError::Timeout` if there
+    /// is no answer within `timeout`.
+    pub async fn execute_command_with_timeout<R: HeosRequest>(
+        &mut self,
+        command: R,
+        timeout: Duration,
+    ) -> HeosResult<R::Response> {
+        let response = self
+            .execute_raw_with_timeout(command, Some(timeout))
+            .await?;
+        R::parse_response(response)
+    }
+
+    /// Executes any command and returns the response as sent by the device.
+    ///
+    /// The command is tagged with a new `SEQUENCE`; an answer echoing a
+    /// different one, or one for another command, is an error. This waits
+    /// for the answer as long as it takes.
+    pub async fn execute_raw<T: Into<CommandPayload>>(
         &mut self,
         command: T,
     ) -> HeosResult<CommandResponse> {
-        let _ = self.write_command(command.into()).await?;
+        self.execute_raw_with_timeout(command, None).await
+    }
+
+    pub async fn execute_raw_with_timeout<T: Into<CommandPayload>>(
+        &mut self,
+        command: T,
+        timeout: Option<Duration>,
+    ) -> HeosResult<CommandResponse> {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        let command = command.into().with_sequence(sequence);
+        let name = command.command_name().to_owned();
+        let uri = command.to_string();
+        // only the wait is timed, a half written command would leave the
+        // connection unusable.
+        self.write_command(command).await?;
+        let answer = self.read_answer(&name, sequence);
+        match timed(&uri, timeout, answer).await {
+            Err(HeosError::InvalidCommand(mut err)) => {
+                err.command = Some(uri);
+                Err(HeosError::InvalidCommand(err))
+            }
+            answer => answer,
+        }
+    }
+
+    // reads until the answer to the command sent with `sequence`. Answers
+    // to earlier commands that timed out are skipped.
+    async fn read_answer(&mut self, name: &str, sequence: Sequence) -> HeosResult<CommandResponse> {
         loop {
             let res = self.read_frame().await?;
             match res {
@@ -58,119 +129,190 @@ impl Connection {
                 Some(Frame::UnderProcess(cmd)) => {
                     trace!("waiting for {}", cmd);
                 }
-                Some(Frame::Response(cmd)) => return Ok(cmd),
-                Some(Frame::Error(err)) => return Err(HeosError::InvalidCommand(err)),
-                _ => {
-                    //
+                Some(Frame::Response(cmd)) if is_late(sequence, cmd.sequence) => {
+                    debug!("dropping late answer to {}", cmd.command_name);
+                }
+                Some(Frame::Error(err)) if is_late(sequence, err.sequence) => {
+                    debug!("dropping late error {}", err);
                 }
+                Some(Frame::Response(cmd)) => {
+                    check_answer(name, sequence, &cmd.command_name, cmd.sequence)?;
+                    return Ok(cmd);
+                }
+                Some(Frame::Error(err)) => {
+                    let failed = err.context.as_deref().unwrap_or_default();
+                    check_answer(name, sequence, failed, err.sequence)?;
+                    return Err(HeosError::InvalidCommand(err));
+                }
+                // use a `HeosClient` to receive events while executing commands.
+                Some(Frame::Event(event)) => debug!("dropping event {:?}", event),
             }
         }
     }
 
     async fn write_command(&mut self, command: CommandPayload) -> HeosResult<()> {
-        let _ = self
-            .stream
-            .write_all(format!("{}\r\n", command).as_bytes())
+        self.framed
+            .send(command)
             .await
             .context("Could not write to connection")?;
-        self.stream.flush().await?;
         Ok(())
     }
 
-    /// Read a single `Frame` value from the underlying stream.
-    ///
-    /// The function waits until it has retrieved enough data to parse a frame.
-    /// Any data remaining in the read buffer after the frame has been parsed is
-    /// kept there for the next call to `read_frame`.
+    /// Reads the next `Frame` from the device.
     ///
-    /// # Returns
-    ///
-    /// On success, the received frame is returned. If the `TcpStream`
-    /// is closed in a way that doesn't break a frame in half, it returns
-    /// `None`. Otherwise, an error is returned.
+    /// Returns `None` once the device closed the connection, or an error if
+    /// it did so in the middle of a frame.
     pub async fn read_frame(&mut self) -> crate::HeosResult<Option<Frame>> {
-        loop {
-            // Attempt to parse a frame from the buffered data. If enough data
-            // has been buffered, the frame is returned.
-            if let Some(frame) = self.parse_frame()? {
-                return Ok(Some(frame));
-            }
+        self.framed.next().await.transpose()
+    }
 
-            // There is not enough buffered data to read a frame. Attempt to
-            // read more data from the socket.
-            //
-            // On success, the number of bytes is returned. `0` indicates "end
-            // of stream".
-            if 0 == self.stream.read_buf(&mut self.buffer).await? {
-                // The remote closed the components.connection. For this to be a clean
-                // shutdown, there should be no data in the read buffer. If
-                // there is, this means that the peer closed the socket while
-                // sending a frame.
-                if self.buffer.is_empty() {
-                    return Ok(None);
-                } else {
-                    return Err(anyhow!("components.connection reset by peer").into());
-                }
+    /// Hands out the framed stream, e.g. to split it into a reading and a
+    /// writing half.
+    pub fn into_framed(self) -> Framed<TcpStream, HeosCodec> {
+        self.framed
+    }
+}
+
+// an answer belongs to a command if it echoes the command's sequence, or
+// if it carries no sequence at all but the command's name.
+fn check_answer(
+    name: &str,
+    sequence: Sequence,
+    answer_name: &str,
+    answer_sequence: Option<Sequence>,
+) -> HeosResult<()> {
+    match answer_sequence {
+        Some(answer_sequence) if answer_sequence != sequence => Err(anyhow!(
+            "got the answer to sequence {} while waiting for {} of {}",
+            answer_sequence,
+            sequence,
+            name
+        )
+        .into()),
+        _ if answer_name != name => Err(anyhow!(
+            "got an answer to {} while waiting for {}",
+            answer_name,
+            name
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+fn is_late(sequence: Sequence, answer_sequence: Option<Sequence>) -> bool {
+    matches!(answer_sequence, Some(answer_sequence) if answer_sequence < sequence)
+}
+
+// fails with `HeosError::Timeout` if `execute` takes longer than `timeout`.
+async fn timed<T, F: Future<Output = HeosResult<T>>>(
+    command: &str,
+    timeout: Option<Duration>,
+    execute: F,
+) -> HeosResult<T> {
+    match timeout {
+        Some(after) => tokio::time::timeout(after, execute)
+            .await
+            .unwrap_or_else(|_| {
+                Err(HeosError::Timeout {
+                    command: command.to_owned(),
+                    after,
+                })
+            }),
+        None => execute.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GetPlayerVolume;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn answers_must_match_the_command() {
+        let name = "player/get_volume";
+        assert!(check_answer(name, 3, name, Some(3)).is_ok());
+        // older firmware doesn't echo the sequence.
+        assert!(check_answer(name, 3, name, None).is_ok());
+
+        let err = check_answer(name, 3, name, Some(4)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "got the answer to sequence 4 while waiting for 3 of player/get_volume"
+        );
+        let err = check_answer(name, 3, "player/get_mute", Some(3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "got an answer to player/get_mute while waiting for player/get_volume"
+        );
+        let err = check_answer(name, 3, "player/get_mute", None).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("got an answer to player/get_mute"));
+    }
+
+    #[test]
+    fn late_answers() {
+        assert!(is_late(3, Some(2)));
+        assert!(!is_late(3, Some(3)));
+        assert!(!is_late(3, Some(4)));
+        assert!(!is_late(3, None));
+    }
+
+    fn volume_answer(line: &str, level: u8) -> String {
+        let params = line.trim_end().split_once('?').unwrap().1;
+        let answer = serde_json::json!({
+            "heos": {
+                "command": "player/get_volume",
+                "result": "success",
+                "message": format!("{}&level={}", params, level),
             }
-        }
+        });
+        format!("{}\r\n", answer)
     }
-    fn parse_frame(&mut self) -> crate::HeosResult<Option<Frame>> {
-        // Cursor is used to track the "current" location in the
-        // buffer. Cursor also implements `Buf` from the `bytes` crate
-        // which provides a number of helpful utilities for working
-        // with bytes.
-        let mut buf = Cursor::new(&self.buffer[..]);
-
-        // The first step is to check if enough data has been buffered to parse
-        // a single frame. This step is usually much faster than doing a full
-        // parse of the frame, and allows us to skip allocating data structures
-        // to hold the frame data unless we know the full frame has been
-        // received.
-        match Frame::check(&mut buf) {
-            Ok(_) => {
-                // The `check` function will have advanced the cursor until the
-                // end of the frame. Since the cursor had position set to zero
-                // before `Frame::check` was called, we obtain the length of the
-                // frame by checking the cursor position.
-                let len = buf.position() as usize;
-
-                // Reset the position to zero before passing the cursor to
-                // `Frame::parse`.
-                buf.set_position(0);
-
-                // Parse the frame from the buffer. This allocates the necessary
-                // structures to represent the frame and returns the frame
-                // value.
-                //
-                // If the encoded frame representation is invalid, an error is
-                // returned. This should terminate the **current** components.connection
-                // but should not impact any other connected client.
-                let frame = Frame::parse(&mut buf)?;
-
-                // Discard the parsed data from the read buffer.
-                //
-                // When `advance` is called on the read buffer, all of the data
-                // up to `len` is discarded. The details of how this works is
-                // left to `BytesMut`. This is often done by moving an internal
-                // cursor, but it may be done by reallocating and copying data.
-                self.buffer.advance(len);
-
-                // Return the parsed frame to the caller.
-                Ok(Some(frame))
+
+    #[tokio::test]
+    async fn late_answers_go_to_nobody() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // answers the first command only after the second one arrived.
+        let device = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let (read, mut write) = socket.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let first = lines.next_line().await.unwrap().unwrap();
+            let second = lines.next_line().await.unwrap().unwrap();
+            write
+                .write_all(volume_answer(&first, 10).as_bytes())
+                .await
+                .unwrap();
+            write
+                .write_all(volume_answer(&second, 20).as_bytes())
+                .await
+                .unwrap();
+        });
+
+        let mut connection = Connection::connect(address).await.unwrap();
+        let timeout = Duration::from_millis(50);
+        let first = GetPlayerVolume { pid: 1 };
+        match connection
+            .execute_command_with_timeout(first, timeout)
+            .await
+        {
+            Err(HeosError::Timeout { command, after }) => {
+                assert!(
+                    command.starts_with("heos://player/get_volume?pid=1"),
+                    "{}",
+                    command
+                );
+                assert_eq!(after, timeout);
             }
-            // There is not enough data present in the read buffer to parse a
-            // single frame. We must wait for more data to be received from the
-            // socket. Reading from the socket will be done in the statement
-            // after this `match`.
-            //
-            // We do not want to return `Err` from here as this "error" is an
-            // expected runtime condition.
-            #[warn(non_snake_case)]
-            Err(_Incomplete) => Ok(None),
-            // An error was encountered while parsing the frame. The components.connection
-            // is now in an invalid state. Returning `Err` from here will result
-            // in the components.connection being closed.
-            // Err(e) => Err(e.into()),
+            other => panic!("expected a timeout, got {:?}", other),
         }
+        let second = GetPlayerVolume { pid: 1 };
+        let level = connection.execute_command_with_timeout(second, Duration::from_secs(2));
+        assert_eq!(level.await.unwrap(), 20);
+        device.await.unwrap();
     }
 }
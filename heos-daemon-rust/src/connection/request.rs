@@ -0,0 +1,538 @@
+//! Typed requests.
+//!
+//! The command enums (`PlayerCommand`, `GroupCommand`, ...) cover the whole
+//! CLI but answer with the raw `CommandResponse`, since one enum can't name
+//! a different answer type per variant. The typed API is the request
+//! structs below, e.g. `execute_command(GetPlayerVolume { pid })` returns a
+//! `Level`. Each of them turns into its enum variant, so the command line
+//! is only written down once.
+//!
+//! The typed commands without parameters aren't variants at all but
+//! constants of their request struct, so `PlayerCommand::GetPlayers` is
+//! `GetPlayers` and answers with a `Vec<PlayerInfo>` either way.
+//!
+//! ```no_run
+//! # async fn example(client: heos_daemon_rust::HeosClient) -> heos_daemon_rust::HeosResult<()> {
+//! use heos_daemon_rust::{GetPlayerVolume, PlayerCommand};
+//!
+//! let players = client.execute_command(PlayerCommand::GetPlayers).await?;
+//! let level = client.execute_command(GetPlayerVolume { pid: 1 }).await?;
+//! let raw = client.execute_command(PlayerCommand::GetPlayerVolume { pid: 1 }).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use serde_json::Value as Json;
+
+use crate::{
+    AccountStatus, BrowseCommand, CommandPayload, CommandResponse, GroupCommand, GroupId,
+    GroupInfo, HeosCommand, HeosResult, Level, NowPlaying, OnOrOff, PlayMode, PlayState,
+    PlayerCommand, PlayerId, PlayerInfo, QueueItem, Range, RawServiceOption, ServiceOption,
+    SystemCommand,
+};
+
+/// A command together with the type of its answer.
+///
+/// Most commands answer with their payload, which is what the default
+/// `parse_response` deserializes. Commands answering in the message, like
+/// `player/get_volume`, override it.
+pub trait HeosRequest: Into<CommandPayload> {
+    type Response: DeserializeOwned;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<Self::Response> {
+        response.parse_payload()
+    }
+}
+
+// the plain command enums are the escape hatch for everything not modelled
+// below, they answer with the untouched response.
+impl HeosRequest for CommandPayload {
+    type Response = CommandResponse;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<CommandResponse> {
+        Ok(response)
+    }
+}
+impl HeosRequest for HeosCommand {
+    type Response = CommandResponse;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<CommandResponse> {
+        Ok(response)
+    }
+}
+impl HeosRequest for SystemCommand {
+    type Response = CommandResponse;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<CommandResponse> {
+        Ok(response)
+    }
+}
+impl HeosRequest for PlayerCommand {
+    type Response = CommandResponse;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<CommandResponse> {
+        Ok(response)
+    }
+}
+impl HeosRequest for GroupCommand {
+    type Response = CommandResponse;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<CommandResponse> {
+        Ok(response)
+    }
+}
+impl HeosRequest for BrowseCommand {
+    type Response = CommandResponse;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<CommandResponse> {
+        Ok(response)
+    }
+}
+
+#[allow(non_upper_case_globals)]
+impl PlayerCommand {
+    pub const GetPlayers: GetPlayers = GetPlayers;
+}
+#[allow(non_upper_case_globals)]
+impl GroupCommand {
+    pub const GetGroups: GetGroups = GetGroups;
+}
+#[allow(non_upper_case_globals)]
+impl SystemCommand {
+    pub const AccountCheck: CheckAccount = CheckAccount;
+    pub const SignOut: SignOut = SignOut;
+}
+
+// reads a single value out of the response message.
+fn message_field<T: DeserializeOwned>(response: CommandResponse, field: &str) -> HeosResult<T> {
+    let value = response
+        .message
+        .get(field)
+        .cloned()
+        .ok_or_else(|| anyhow!("missing {} in message of {}", field, &response.command_name))?;
+    let value = serde_json::from_value(value).context(format!(
+        "could not parse {} of {}",
+        field, &response.command_name
+    ))?;
+    Ok(value)
+}
+
+pub struct GetPlayers;
+impl From<GetPlayers> for CommandPayload {
+    fn from(_: GetPlayers) -> Self {
+        CommandPayload::raw("player/get_players")
+    }
+}
+impl HeosRequest for GetPlayers {
+    type Response = Vec<PlayerInfo>;
+}
+
+pub struct GetPlayerInfo {
+    pub pid: PlayerId,
+}
+impl From<GetPlayerInfo> for CommandPayload {
+    fn from(request: GetPlayerInfo) -> Self {
+        PlayerCommand::GetPlayerInfo { pid: request.pid }.into()
+    }
+}
+impl HeosRequest for GetPlayerInfo {
+    type Response = PlayerInfo;
+}
+
+pub struct GetPlayState {
+    pub pid: PlayerId,
+}
+impl From<GetPlayState> for CommandPayload {
+    fn from(request: GetPlayState) -> Self {
+        PlayerCommand::GetPlayState { pid: request.pid }.into()
+    }
+}
+impl HeosRequest for GetPlayState {
+    type Response = PlayState;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<PlayState> {
+        message_field(response, "state")
+    }
+}
+
+pub struct GetNowPlayingMedia {
+    pub pid: PlayerId,
+}
+impl From<GetNowPlayingMedia> for CommandPayload {
+    fn from(request: GetNowPlayingMedia) -> Self {
+        PlayerCommand::GetNowPlayingMedia { pid: request.pid }.into()
+    }
+}
+impl HeosRequest for GetNowPlayingMedia {
+    type Response = NowPlaying;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<NowPlaying> {
+        // a player that never played anything answers with an empty payload.
+        let media = match response.payload.get("type") {
+            Some(_) => Some(response.parse_payload()?),
+            None => None,
+        };
+        // `[{"play": [{"id": 19, "name": "Add to HEOS Favorites"}]}]`
+        let options: Vec<BTreeMap<String, Vec<RawServiceOption>>> = match response.options {
+            Json::Null => Vec::new(),
+            ref options => serde_json::from_value(options.clone()).context(format!(
+                "could not parse options of {}",
+                &response.command_name
+            ))?,
+        };
+        Ok(NowPlaying {
+            media,
+            options: options
+                .into_iter()
+                .flat_map(BTreeMap::into_values)
+                .flatten()
+                .map(ServiceOption::from)
+                .collect(),
+        })
+    }
+}
+
+pub struct GetPlayerVolume {
+    pub pid: PlayerId,
+}
+impl From<GetPlayerVolume> for CommandPayload {
+    fn from(request: GetPlayerVolume) -> Self {
+        PlayerCommand::GetPlayerVolume { pid: request.pid }.into()
+    }
+}
+impl HeosRequest for GetPlayerVolume {
+    type Response = Level;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<Level> {
+        message_field(response, "level")
+    }
+}
+
+pub struct GetPlayerMute {
+    pub pid: PlayerId,
+}
+impl From<GetPlayerMute> for CommandPayload {
+    fn from(request: GetPlayerMute) -> Self {
+        PlayerCommand::GetMute { pid: request.pid }.into()
+    }
+}
+impl HeosRequest for GetPlayerMute {
+    type Response = OnOrOff;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<OnOrOff> {
+        message_field(response, "state")
+    }
+}
+
+pub struct GetPlayMode {
+    pub pid: PlayerId,
+}
+impl From<GetPlayMode> for CommandPayload {
+    fn from(request: GetPlayMode) -> Self {
+        PlayerCommand::GetPlayMode { pid: request.pid }.into()
+    }
+}
+impl HeosRequest for GetPlayMode {
+    type Response = PlayMode;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<PlayMode> {
+        response.parse_message()
+    }
+}
+
+pub struct GetQueue {
+    pub pid: PlayerId,
+    pub range: Option<Range>,
+}
+impl From<GetQueue> for CommandPayload {
+    fn from(request: GetQueue) -> Self {
+        PlayerCommand::GetQueue {
+            pid: request.pid,
+            range: request.range,
+        }
+        .into()
+    }
+}
+impl HeosRequest for GetQueue {
+    type Response = Vec<QueueItem>;
+}
+
+pub struct GetGroups;
+impl From<GetGroups> for CommandPayload {
+    fn from(_: GetGroups) -> Self {
+        CommandPayload::raw("group/get_groups")
+    }
+}
+impl HeosRequest for GetGroups {
+    type Response = Vec<GroupInfo>;
+}
+
+pub struct GetGroupInfo {
+    pub gid: GroupId,
+}
+impl From<GetGroupInfo> for CommandPayload {
+    fn from(request: GetGroupInfo) -> Self {
+        GroupCommand::GetGroupInfo { gid: request.gid }.into()
+    }
+}
+impl HeosRequest for GetGroupInfo {
+    type Response = GroupInfo;
+}
+
+pub struct GetGroupVolume {
+    pub gid: GroupId,
+}
+impl From<GetGroupVolume> for CommandPayload {
+    fn from(request: GetGroupVolume) -> Self {
+        GroupCommand::GetGroupVolume { gid: request.gid }.into()
+    }
+}
+impl HeosRequest for GetGroupVolume {
+    type Response = Level;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<Level> {
+        message_field(response, "level")
+    }
+}
+
+pub struct GetGroupMute {
+    pub gid: GroupId,
+}
+impl From<GetGroupMute> for CommandPayload {
+    fn from(request: GetGroupMute) -> Self {
+        GroupCommand::GetMute { gid: request.gid }.into()
+    }
+}
+impl HeosRequest for GetGroupMute {
+    type Response = OnOrOff;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<OnOrOff> {
+        message_field(response, "state")
+    }
+}
+
+pub struct CheckAccount;
+impl From<CheckAccount> for CommandPayload {
+    fn from(_: CheckAccount) -> Self {
+        CommandPayload::raw("system/check_account")
+    }
+}
+impl HeosRequest for CheckAccount {
+    type Response = AccountStatus;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<AccountStatus> {
+        account_status(response)
+    }
+}
+
+// the client remembers the credentials to sign in again after a reconnect.
+pub struct SignIn {
+    pub un: String,
+    pub pw: String,
+}
+impl From<SignIn> for CommandPayload {
+    fn from(request: SignIn) -> Self {
+        SystemCommand::SignIn {
+            un: request.un,
+            pw: request.pw,
+        }
+        .into()
+    }
+}
+impl HeosRequest for SignIn {
+    type Response = AccountStatus;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<AccountStatus> {
+        account_status(response)
+    }
+}
+
+pub struct SignOut;
+impl From<SignOut> for CommandPayload {
+    fn from(_: SignOut) -> Self {
+        CommandPayload::raw("system/sign_out")
+    }
+}
+impl HeosRequest for SignOut {
+    type Response = AccountStatus;
+
+    fn parse_response(response: CommandResponse) -> HeosResult<AccountStatus> {
+        account_status(response)
+    }
+}
+
+// `signed_in&un=me@example.com` or `signed_out`.
+fn account_status(response: CommandResponse) -> HeosResult<AccountStatus> {
+    let message = &response.message;
+    if message.get("signed_in").is_some() {
+        let un = match message.get("un") {
+            Some(serde_json::Value::String(un)) => un.clone(),
+            // a user name can look like a number.
+            Some(un) => un.to_string(),
+            None => String::new(),
+        };
+        Ok(AccountStatus::SignedIn(un))
+    } else if message.get("signed_out").is_some() {
+        Ok(AccountStatus::SignedOut)
+    } else {
+        Err(anyhow!("unexpected account status {}", message).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::response_line::qs_to_json;
+    use super::*;
+    use crate::NowPlayingMedia;
+
+    // the response as parsed from `message` and `payload` on the wire.
+    fn response(command_name: &str, message: &str, payload: Json) -> CommandResponse {
+        CommandResponse {
+            command_name: command_name.to_owned(),
+            message: qs_to_json(message),
+            payload,
+            options: Json::Null,
+            sequence: None,
+        }
+    }
+
+    fn now_playing(payload: Json, options: Json) -> NowPlaying {
+        let mut response = response("player/get_now_playing_media", "pid=1", payload);
+        response.options = options;
+        GetNowPlayingMedia::parse_response(response).unwrap()
+    }
+
+    #[test]
+    fn now_playing_song() {
+        let payload = serde_json::json!({
+            "type": "song",
+            "song": "Song",
+            "album": "Album",
+            "artist": "Artist",
+            "image_url": "http://example.com/cover.jpg",
+            "album_id": 42,
+            "mid": "1234",
+            "qid": "3",
+            "sid": 1024
+        });
+        let playing = now_playing(payload, Json::Null);
+        assert_eq!(
+            playing.media,
+            Some(NowPlayingMedia::Song {
+                song: "Song".to_owned(),
+                album: "Album".to_owned(),
+                artist: "Artist".to_owned(),
+                image_url: "http://example.com/cover.jpg".to_owned(),
+                album_id: "42".to_owned(),
+                mid: "1234".to_owned(),
+                qid: Some(3),
+                sid: 1024,
+            })
+        );
+        assert!(playing.options.is_empty());
+    }
+
+    #[test]
+    fn now_playing_station() {
+        let payload = serde_json::json!({
+            "type": "station",
+            "song": "Song",
+            "station": "Radio",
+            "artist": "Artist",
+            "mid": "s1234",
+            "sid": "3"
+        });
+        let media = now_playing(payload, Json::Null).media.unwrap();
+        assert!(
+            matches!(&media, NowPlayingMedia::Station { station, qid: None, .. } if station == "Radio")
+        );
+        assert_eq!(media.song(), "Song");
+        assert_eq!(media.album(), "");
+        assert_eq!(media.sid(), 3);
+    }
+
+    #[test]
+    fn nothing_playing() {
+        let playing = now_playing(serde_json::json!({}), Json::Null);
+        assert_eq!(playing.media, None);
+        assert!(playing.options.is_empty());
+        assert_eq!(now_playing(Json::Null, Json::Null).media, None);
+    }
+
+    #[test]
+    fn now_playing_options() {
+        let options = serde_json::json!([{
+            "play": [
+                { "id": 19, "name": "Add to HEOS Favorites" },
+                { "id": "11", "name": "Thumbs Up" },
+                { "id": 99, "name": "Something new" }
+            ]
+        }]);
+        let playing = now_playing(serde_json::json!({}), options);
+        assert_eq!(
+            playing.options,
+            [
+                ServiceOption::AddToFavorites,
+                ServiceOption::ThumbsUp,
+                ServiceOption::Other {
+                    id: 99,
+                    name: "Something new".to_owned()
+                },
+            ]
+        );
+        // what the api serves can be read back.
+        let json = serde_json::to_value(&playing).unwrap();
+        assert_eq!(json["options"][0], "add_to_favorites");
+        assert_eq!(serde_json::from_value::<NowPlaying>(json).unwrap(), playing);
+    }
+
+    #[test]
+    fn typed_commands_without_parameters() {
+        let wire = |command: CommandPayload| command.to_string();
+        assert_eq!(
+            wire(PlayerCommand::GetPlayers.into()),
+            "heos://player/get_players"
+        );
+        assert_eq!(
+            wire(GroupCommand::GetGroups.into()),
+            "heos://group/get_groups"
+        );
+        assert_eq!(
+            wire(SystemCommand::AccountCheck.into()),
+            "heos://system/check_account"
+        );
+        assert_eq!(
+            wire(SystemCommand::SignOut.into()),
+            "heos://system/sign_out"
+        );
+    }
+
+    #[test]
+    fn account_statuses() {
+        let signed_in = response(
+            "system/check_account",
+            "signed_in&un=me@example.com",
+            Json::Null,
+        );
+        assert_eq!(
+            CheckAccount::parse_response(signed_in).unwrap(),
+            AccountStatus::SignedIn("me@example.com".to_owned())
+        );
+        let numeric = response("system/sign_in", "signed_in&un=1234", Json::Null);
+        assert_eq!(
+            SignIn::parse_response(numeric).unwrap(),
+            AccountStatus::SignedIn("1234".to_owned())
+        );
+        let signed_out = response("system/sign_out", "signed_out", Json::Null);
+        assert_eq!(
+            SignOut::parse_response(signed_out).unwrap(),
+            AccountStatus::SignedOut
+        );
+        let unexpected = response("system/check_account", "maybe", Json::Null);
+        assert!(CheckAccount::parse_response(unexpected).is_err());
+    }
+}
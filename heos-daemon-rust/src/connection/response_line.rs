@@ -1,58 +1,74 @@
 use crate::error::HeosErrorCode;
-use crate::types::*;
-use crate::HeosCommand::Player;
-use anyhow::anyhow;
+use crate::Sequence;
 use serde::Deserialize;
 use serde_json::{Map, Value};
-use std::collections::BTreeMap;
 
 // this turns the rather strange query string into a json object
 // nice to easy parsing upstream.
+//
+// every `key=value` pair becomes a field; values that are integers become
+// numbers, everything else a string. Keys without a value, like the
+// `signed_in` in `signed_in&un=me@example.com`, become `true`. The echoed
+// `SEQUENCE` is left out, see `sequence`.
 pub fn qs_to_json(message: &str) -> Value {
-    use serde_json::*;
     if message.is_empty() {
-        Value::Null
-    } else {
-        // remove spaces to make it a valid url encoded string.
-        let clean_message = message.replace(" ", "%20");
-        let value = qs::from_str::<EventQueryParams>(&clean_message);
-        match value {
-            Ok(params) => remove_null(serde_json::to_value(params).unwrap()),
-            Err(err) => {
-                Value::String(message.to_owned())
-            }
-        }
+        return Value::Null;
+    }
+    let mut fields = Map::new();
+    for part in message.split('&').filter(|part| !part.is_empty()) {
+        let (key, value) = match part.split_once('=') {
+            Some(("SEQUENCE", _)) => continue,
+            Some((key, value)) => (key, parse_value(&unescape(value))),
+            None => (part, Value::Bool(true)),
+        };
+        fields.insert(unescape(key), value);
     }
+    Value::Object(fields)
 }
 
-// this is used to collect all possible parameters in heos strange query string format
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Default)]
-struct EventQueryParams {
-    pid: Option<i64>,
-    gid: Option<i64>,
-    level: Option<u8>,
-    mute: Option<OnOrOff>,
-    shuffle: Option<OnOrOff>,
-    repeat: Option<Repeat>,
-    un: Option<String>,
-    text: Option<String>,
-    eid: Option<u8>,
-    cur_pos: Option<Milliseconds>,
-    duration: Option<Milliseconds>,
-    state: Option<PlayState>,
+// the `SEQUENCE=<n>` a command was tagged with, as echoed in its answer.
+pub fn sequence(message: &str) -> Option<Sequence> {
+    message
+        .split('&')
+        .find_map(|part| part.strip_prefix("SEQUENCE="))
+        .and_then(|sequence| sequence.parse().ok())
 }
 
-// remove all null values from the object!
-// TODO maybe use also https://github.com/jmfiaschi/json_value_merge/blob/main/src/lib.rs
-pub fn remove_null(value: Value) -> Value {
-    fn non_null_object(entries: Map<String, Value>) -> Value {
-        let clean = entries.into_iter().filter(|e| !e.1.is_null()).collect();
-        Value::Object(clean)
+// only canonical integers are numbers, so ids like `0042` stay strings.
+fn parse_value(value: &str) -> Value {
+    match value.parse::<i64>() {
+        Ok(number) if number.to_string() == value => Value::from(number),
+        _ => Value::String(value.to_owned()),
     }
-    match value {
-        Value::Object(map) => non_null_object(map),
-        other => other,
+}
+
+/// Reverses `escape`: `%26`, `%3D` and `%25` become `&`, `=` and `%`.
+/// Anything else, including a lone `%`, is kept as it is.
+pub fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('%') {
+        unescaped.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let decoded = match rest.get(..3).map(str::to_ascii_uppercase).as_deref() {
+            Some("%26") => Some('&'),
+            Some("%3D") => Some('='),
+            Some("%25") => Some('%'),
+            _ => None,
+        };
+        match decoded {
+            Some(c) => {
+                unescaped.push(c);
+                rest = &rest[3..];
+            }
+            None => {
+                unescaped.push('%');
+                rest = &rest[1..];
+            }
+        }
     }
+    unescaped.push_str(rest);
+    unescaped
 }
 
 impl<'de> Deserialize<'de> for HeosErrorCode {
@@ -60,23 +76,81 @@ impl<'de> Deserialize<'de> for HeosErrorCode {
     where
         D: serde::Deserializer<'de>,
     {
-        match i8::deserialize(deserializer)? {
-            1 => Ok(HeosErrorCode::UnrecognizedCommand),
-            2 => Ok(HeosErrorCode::InvalidId),
-            3 => Ok(HeosErrorCode::WrongNumberOfArguments),
-            4 => Ok(HeosErrorCode::RequestedDataNotAvailable),
-            5 => Ok(HeosErrorCode::ResourceCurrentlyNotAvailable),
-            6 => Ok(HeosErrorCode::InvalidCredentials),
-            7 => Ok(HeosErrorCode::CommandCouldNitBeExecuted),
-            8 => Ok(HeosErrorCode::UserNotLoggedIn),
-            9 => Ok(HeosErrorCode::ParameterOutOfRange),
-            10 => Ok(HeosErrorCode::UserNotFound),
-            11 => Ok(HeosErrorCode::InternalError),
-            12 => Ok(HeosErrorCode::SystemError),
-            13 => Ok(HeosErrorCode::ProcessingPreviousCommand),
-            14 => Ok(HeosErrorCode::MediaCantBePlayed),
-            15 => Ok(HeosErrorCode::OptionNotSupported),
-            _ => Ok(HeosErrorCode::Unknown),
+        let eid = i64::deserialize(deserializer)?;
+        Ok(u8::try_from(eid).map_or(HeosErrorCode::Unknown, HeosErrorCode::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn messages_of_every_family() {
+        let cases = vec![
+            (
+                "signed_in&un=me@example.com",
+                json!({ "signed_in": true, "un": "me@example.com" }),
+            ),
+            ("signed_out", json!({ "signed_out": true })),
+            (
+                "eid=12&text=System error&syserrno=-9",
+                json!({ "eid": 12, "text": "System error", "syserrno": -9 }),
+            ),
+            (
+                "pid=-1146526683&level=25",
+                json!({ "pid": -1146526683, "level": 25 }),
+            ),
+            ("pid=1&state=play", json!({ "pid": 1, "state": "play" })),
+            (
+                "pid=1&repeat=on_all&shuffle=off",
+                json!({ "pid": 1, "repeat": "on_all", "shuffle": "off" }),
+            ),
+            ("gid=-5&mute=on", json!({ "gid": -5, "mute": "on" })),
+            (
+                "sid=10&cid=Artists&range=0,9&returned=10&count=30",
+                json!({ "sid": 10, "cid": "Artists", "range": "0,9", "returned": 10, "count": 30 }),
+            ),
+            (
+                "sid=10&search=Simon %26 Garfunkel&scid=1",
+                json!({ "sid": 10, "search": "Simon & Garfunkel", "scid": 1 }),
+            ),
+            (
+                "pid=1&cur_pos=62000&duration=228000",
+                json!({ "pid": 1, "cur_pos": 62000, "duration": 228000 }),
+            ),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(qs_to_json(message), expected, "{}", message);
         }
     }
+
+    #[test]
+    fn sequence_is_stripped() {
+        let message = "pid=1&level=25&SEQUENCE=42";
+        assert_eq!(qs_to_json(message), json!({ "pid": 1, "level": 25 }));
+        assert_eq!(sequence(message), Some(42));
+        assert_eq!(qs_to_json("SEQUENCE=7"), json!({}));
+        assert_eq!(sequence("pid=1"), None);
+    }
+
+    #[test]
+    fn only_canonical_integers_are_numbers() {
+        let message = "mid=0042&qid=+3&album_id=12a&level=0&sid=-0";
+        let expected = json!({
+            "mid": "0042",
+            "qid": "+3",
+            "album_id": "12a",
+            "level": 0,
+            "sid": "-0",
+        });
+        assert_eq!(qs_to_json(message), expected);
+    }
+
+    #[test]
+    fn empty_message() {
+        assert_eq!(qs_to_json(""), Value::Null);
+        assert_eq!(qs_to_json("&&"), json!({}));
+    }
 }
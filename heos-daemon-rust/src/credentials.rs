@@ -0,0 +1,175 @@
+use std::env;
+use std::fmt;
+use std::fs;
+
+use anyhow::{anyhow, Context};
+use heos_daemon_rust::{AccountStatus, HeosClient, HeosResult, SignIn};
+use tracing::{info, warn};
+
+/// The HEOS account the daemon signs in with.
+///
+/// Read from `HEOS_USERNAME` and `HEOS_PASSWORD`, or else from the file
+/// named by `HEOS_CREDENTIALS_FILE` containing `username=...` and
+/// `password=...` lines.
+pub struct Credentials {
+    pub username: String,
+    password: String,
+}
+
+// never show the password.
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Credentials")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+impl Credentials {
+    /// `None` if no credentials are configured at all.
+    pub fn load() -> HeosResult<Option<Credentials>> {
+        if let (Ok(username), Ok(password)) = (env::var("HEOS_USERNAME"), env::var("HEOS_PASSWORD"))
+        {
+            return Ok(Some(Credentials { username, password }));
+        }
+        match env::var("HEOS_CREDENTIALS_FILE") {
+            Ok(path) => Self::read(&path).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn read(path: &str) -> HeosResult<Credentials> {
+        warn_if_readable_by_others(path);
+        let content =
+            fs::read_to_string(path).context(format!("could not read credentials {}", path))?;
+        let mut username = None;
+        let mut password = None;
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("username", value)) => username = Some(value.trim().to_owned()),
+                Some(("password", value)) => password = Some(value.trim().to_owned()),
+                _ => warn!("ignoring unknown line in {}", path),
+            }
+        }
+        match (username, password) {
+            (Some(username), Some(password)) => Ok(Credentials { username, password }),
+            _ => Err(anyhow!("{} needs a username and a password", path).into()),
+        }
+    }
+
+    /// Signs in; the client signs in again by itself after reconnecting.
+    pub async fn sign_in(self, client: &HeosClient) -> HeosResult<()> {
+        let status = client
+            .execute_command(SignIn {
+                un: self.username,
+                pw: self.password,
+            })
+            .await?;
+        match status {
+            AccountStatus::SignedIn(username) => info!("signed in as {}", username),
+            AccountStatus::SignedOut => warn!("still signed out after signing in"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(path: &str) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().mode() & 0o077 != 0 {
+            warn!("{} is accessible by other users, consider chmod 600", path);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_path: &str) {}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // a file in the temp dir that is removed again when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, content: &str) -> TempFile {
+            let path = env::temp_dir().join(format!("{}-{}", std::process::id(), name));
+            fs::write(&path, content).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_the_file() {
+        let file = TempFile::new(
+            "credentials",
+            "# my account\nusername=me@example.com\n\npassword=s3cret \n",
+        );
+        let credentials = Credentials::read(file.path()).unwrap();
+        assert_eq!(credentials.username, "me@example.com");
+        assert_eq!(credentials.password, "s3cret");
+
+        let incomplete = TempFile::new("incomplete", "username=me@example.com\n");
+        assert!(Credentials::read(incomplete.path()).is_err());
+        assert!(Credentials::read("/nonexistent/credentials").is_err());
+    }
+
+    // one test, the environment is shared by all of them.
+    #[test]
+    fn environment_before_the_file() {
+        let file = TempFile::new("load", "username=file@example.com\npassword=from file\n");
+        for name in ["HEOS_USERNAME", "HEOS_PASSWORD", "HEOS_CREDENTIALS_FILE"] {
+            env::remove_var(name);
+        }
+        assert!(Credentials::load().unwrap().is_none());
+
+        env::set_var("HEOS_CREDENTIALS_FILE", file.path());
+        let credentials = Credentials::load().unwrap().unwrap();
+        assert_eq!(credentials.username, "file@example.com");
+
+        // both variables are needed to take precedence.
+        env::set_var("HEOS_USERNAME", "env@example.com");
+        let credentials = Credentials::load().unwrap().unwrap();
+        assert_eq!(credentials.username, "file@example.com");
+        env::set_var("HEOS_PASSWORD", "from env");
+        let credentials = Credentials::load().unwrap().unwrap();
+        assert_eq!(credentials.username, "env@example.com");
+        assert_eq!(credentials.password, "from env");
+
+        env::remove_var("HEOS_USERNAME");
+        env::remove_var("HEOS_PASSWORD");
+        env::set_var("HEOS_CREDENTIALS_FILE", "/nonexistent/credentials");
+        assert!(Credentials::load().is_err());
+        env::remove_var("HEOS_CREDENTIALS_FILE");
+    }
+
+    #[test]
+    fn password_is_not_shown() {
+        let credentials = Credentials {
+            username: "me@example.com".to_owned(),
+            password: "s3cret".to_owned(),
+        };
+        assert_eq!(
+            format!("{:?}", credentials),
+            r#"Credentials { username: "me@example.com", password: "***" }"#
+        );
+    }
+}
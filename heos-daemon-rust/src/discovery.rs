@@ -0,0 +1,304 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::{HeosError, HeosResult};
+
+/// Multicast address and port every SSDP search is sent to.
+pub const SSDP_ADDR: &str = "239.255.255.250:1900";
+/// The search target HEOS devices answer to.
+pub const HEOS_URN: &str = "urn:schemas-denon-com:device:ACT-Denon:1";
+/// Port of the HEOS CLI on every device.
+pub const HEOS_PORT: u16 = 1255;
+
+/// A HEOS device that answered an SSDP search.
+///
+/// The description fields are read from the XML document the device
+/// announces via `LOCATION` and are `None` if it could not be fetched.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub address: IpAddr,
+    pub location: String,
+    pub usn: Option<String>,
+    pub friendly_name: Option<String>,
+    pub manufacturer: Option<String>,
+    pub model_name: Option<String>,
+    pub model_number: Option<String>,
+    pub serial_number: Option<String>,
+    pub udn: Option<String>,
+}
+
+impl DiscoveredDevice {
+    /// The address of the HEOS CLI on this device.
+    pub fn heos_address(&self) -> SocketAddr {
+        SocketAddr::new(self.address, HEOS_PORT)
+    }
+}
+
+/// Searches the local network for HEOS devices and returns the first one
+/// that answers within `timeout`.
+pub async fn discover(timeout: Duration) -> HeosResult<DiscoveredDevice> {
+    discover_at(SSDP_ADDR, timeout).await
+}
+
+/// Like [`discover`] but sends the M-SEARCH to `target` instead of the SSDP
+/// multicast group, e.g. to a responder on loopback.
+pub async fn discover_at<A: ToSocketAddrs>(
+    target: A,
+    timeout: Duration,
+) -> HeosResult<DiscoveredDevice> {
+    let deadline = Instant::now() + timeout;
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .send_to(search_request(timeout).as_bytes(), target)
+        .await
+        .context("Could not send ssdp search")?;
+
+    let mut buf = [0u8; 2048];
+    loop {
+        let (len, from) = match tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await
+        {
+            Ok(received) => received?,
+            Err(_) => return Err(HeosError::NoDevicesFound),
+        };
+        let response = String::from_utf8_lossy(&buf[..len]);
+        match SearchResponse::parse(&response) {
+            Some(response) => {
+                debug!("ssdp response from {}: {:?}", from, response);
+                return Ok(response.into_device(from.ip(), deadline).await);
+            }
+            None => debug!("ignoring ssdp message from {}", from),
+        }
+    }
+}
+
+fn search_request(timeout: Duration) -> String {
+    format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: {}\r\n\
+         ST: {}\r\n\
+         \r\n",
+        SSDP_ADDR,
+        timeout.as_secs().clamp(1, 5),
+        HEOS_URN
+    )
+}
+
+// the parts of an ssdp answer we care about.
+#[derive(Debug)]
+struct SearchResponse {
+    location: String,
+    usn: Option<String>,
+}
+
+impl SearchResponse {
+    fn parse(message: &str) -> Option<SearchResponse> {
+        let mut lines = message.lines();
+        if !lines.next()?.starts_with("HTTP/1.1 200") {
+            return None;
+        }
+        let mut location = None;
+        let mut usn = None;
+        let mut search_target = None;
+        for line in lines {
+            if let Some((name, value)) = line.split_once(':') {
+                let value = value.trim().to_owned();
+                match name.trim().to_ascii_uppercase().as_str() {
+                    "LOCATION" => location = Some(value),
+                    "USN" => usn = Some(value),
+                    "ST" => search_target = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        if search_target.as_deref() != Some(HEOS_URN) {
+            return None;
+        }
+        Some(SearchResponse {
+            location: location?,
+            usn,
+        })
+    }
+
+    // the description is only fetched until `deadline`, a device that
+    // stalls is returned without it.
+    async fn into_device(self, from: IpAddr, deadline: Instant) -> DiscoveredDevice {
+        let address = location_host(&self.location)
+            .and_then(|(host, _)| host.parse().ok())
+            .unwrap_or(from);
+        let mut device = DiscoveredDevice {
+            address,
+            location: self.location,
+            usn: self.usn,
+            friendly_name: None,
+            manufacturer: None,
+            model_name: None,
+            model_number: None,
+            serial_number: None,
+            udn: None,
+        };
+        let description =
+            tokio::time::timeout_at(deadline, fetch_description(&device.location)).await;
+        match description.unwrap_or_else(|_| Err(anyhow!("timed out").into())) {
+            Ok(xml) => {
+                device.friendly_name = xml_tag(&xml, "friendlyName");
+                device.manufacturer = xml_tag(&xml, "manufacturer");
+                device.model_name = xml_tag(&xml, "modelName");
+                device.model_number = xml_tag(&xml, "modelNumber");
+                device.serial_number = xml_tag(&xml, "serialNumber");
+                device.udn = xml_tag(&xml, "UDN");
+            }
+            Err(err) => warn!("could not read description {}: {}", device.location, err),
+        }
+        device
+    }
+}
+
+// splits `http://host:port/path` into its authority and path.
+fn location_host(location: &str) -> Option<(&str, u16)> {
+    let rest = location.strip_prefix("http://")?;
+    let authority = rest.split('/').next()?;
+    match authority.rsplit_once(':') {
+        Some((host, port)) => Some((host, port.parse().ok()?)),
+        None => Some((authority, 80)),
+    }
+}
+
+// a tiny http client; the description is the only thing we ever fetch.
+async fn fetch_description(location: &str) -> HeosResult<String> {
+    let (host, port) =
+        location_host(location).ok_or_else(|| anyhow!("unsupported location {}", location))?;
+    let path = location
+        .trim_start_matches("http://")
+        .find('/')
+        .map(|i| &location["http://".len() + i..])
+        .unwrap_or("/");
+    let mut stream = TcpStream::connect((host, port)).await?;
+    stream
+        .write_all(format!("GET {} HTTP/1.0\r\nHost: {}:{}\r\n\r\n", path, host, port).as_bytes())
+        .await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    match response.split_once("\r\n\r\n") {
+        Some((head, body))
+            if head.starts_with("HTTP/1.1 200") || head.starts_with("HTTP/1.0 200") =>
+        {
+            Ok(body.to_owned())
+        }
+        _ => Err(anyhow!("unexpected description response from {}", location).into()),
+    }
+}
+
+// returns the text of the first `<tag>` element, which is all the
+// description document needs.
+fn xml_tag(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = start + xml[start..].find(&close)?;
+    Some(xml[start..end].trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    // answers the first search with `location`.
+    async fn responder(location: String) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let address = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            let (_, from) = socket.recv_from(&mut buf).await.unwrap();
+            let answer = format!(
+                "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=180\r\nLOCATION: {}\r\n\
+                 ST: {}\r\nUSN: uuid:1234::{}\r\n\r\n",
+                location, HEOS_URN, HEOS_URN
+            );
+            // something that isn't a heos answer comes first.
+            socket
+                .send_to(b"NOTIFY * HTTP/1.1\r\n\r\n", from)
+                .await
+                .unwrap();
+            socket.send_to(answer.as_bytes(), from).await.unwrap();
+        });
+        address
+    }
+
+    // serves `response` to every request, or never answers if it is `None`.
+    async fn description_server(response: Option<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                match response {
+                    Some(response) => {
+                        let mut buf = [0u8; 1024];
+                        let _ = socket.read(&mut buf).await;
+                        let _ = socket.write_all(response.as_bytes()).await;
+                    }
+                    None => {
+                        tokio::spawn(async move {
+                            tokio::time::sleep(Duration::from_secs(60)).await;
+                            drop(socket);
+                        });
+                    }
+                }
+            }
+        });
+        format!("http://{}/upnp/desc/aios_device/aios_device.xml", address)
+    }
+
+    #[tokio::test]
+    async fn reads_the_description() {
+        let location = description_server(Some(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n\
+             <root><device><friendlyName>Living Room</friendlyName>\
+             <modelName>HEOS 7</modelName><UDN>uuid:1234</UDN></device></root>",
+        ))
+        .await;
+        let target = responder(location.clone()).await;
+        let device = discover_at(target, Duration::from_secs(2)).await.unwrap();
+        assert_eq!(device.address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(device.location, location);
+        assert_eq!(
+            device.usn.as_deref(),
+            Some("uuid:1234::urn:schemas-denon-com:device:ACT-Denon:1")
+        );
+        assert_eq!(device.friendly_name.as_deref(), Some("Living Room"));
+        assert_eq!(device.model_name.as_deref(), Some("HEOS 7"));
+        assert_eq!(device.udn.as_deref(), Some("uuid:1234"));
+        assert_eq!(device.manufacturer, None);
+    }
+
+    #[tokio::test]
+    async fn stalled_description_gives_a_bare_device() {
+        let location = description_server(None).await;
+        let target = responder(location).await;
+        let started = Instant::now();
+        let device = discover_at(target, Duration::from_millis(300))
+            .await
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(device.address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(device.friendly_name, None);
+    }
+
+    #[tokio::test]
+    async fn nobody_answering() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = silent.local_addr().unwrap();
+        let err = discover_at(target, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, HeosError::NoDevicesFound));
+    }
+}
@@ -1,7 +1,8 @@
-use qs;
+use std::fmt;
+use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Debug, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
 pub enum HeosErrorCode {
     UnrecognizedCommand = 1,
     InvalidId = 2,
@@ -21,11 +22,98 @@ pub enum HeosErrorCode {
     Unknown,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl HeosErrorCode {
+    /// Whether sending the same command again later may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            HeosErrorCode::ProcessingPreviousCommand | HeosErrorCode::ResourceCurrentlyNotAvailable
+        )
+    }
+}
+
+impl From<u8> for HeosErrorCode {
+    fn from(eid: u8) -> Self {
+        match eid {
+            1 => HeosErrorCode::UnrecognizedCommand,
+            2 => HeosErrorCode::InvalidId,
+            3 => HeosErrorCode::WrongNumberOfArguments,
+            4 => HeosErrorCode::RequestedDataNotAvailable,
+            5 => HeosErrorCode::ResourceCurrentlyNotAvailable,
+            6 => HeosErrorCode::InvalidCredentials,
+            7 => HeosErrorCode::CommandCouldNitBeExecuted,
+            8 => HeosErrorCode::UserNotLoggedIn,
+            9 => HeosErrorCode::ParameterOutOfRange,
+            10 => HeosErrorCode::UserNotFound,
+            11 => HeosErrorCode::InternalError,
+            12 => HeosErrorCode::SystemError,
+            13 => HeosErrorCode::ProcessingPreviousCommand,
+            14 => HeosErrorCode::MediaCantBePlayed,
+            15 => HeosErrorCode::OptionNotSupported,
+            _ => HeosErrorCode::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for HeosErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            HeosErrorCode::UnrecognizedCommand => "unrecognized command",
+            HeosErrorCode::InvalidId => "invalid id",
+            HeosErrorCode::WrongNumberOfArguments => "wrong number of command arguments",
+            HeosErrorCode::RequestedDataNotAvailable => "requested data not available",
+            HeosErrorCode::ResourceCurrentlyNotAvailable => "resource currently not available",
+            HeosErrorCode::InvalidCredentials => "invalid credentials",
+            HeosErrorCode::CommandCouldNitBeExecuted => "command could not be executed",
+            HeosErrorCode::UserNotLoggedIn => "user not logged in",
+            HeosErrorCode::ParameterOutOfRange => "parameter out of range",
+            HeosErrorCode::UserNotFound => "user not found",
+            HeosErrorCode::InternalError => "internal error",
+            HeosErrorCode::SystemError => "system error",
+            HeosErrorCode::ProcessingPreviousCommand => "processing previous command",
+            HeosErrorCode::MediaCantBePlayed => "media can't be played",
+            HeosErrorCode::OptionNotSupported => "option not supported",
+            HeosErrorCode::Unknown => "unknown error",
+        };
+        match self {
+            HeosErrorCode::Unknown => write!(f, "{}", text),
+            code => write!(f, "{} (eid {})", text, *code as u8),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorMessage {
     pub eid: HeosErrorCode,
     pub text: String,
+    // the name of the failed command, e.g. `player/get_volume`.
     pub context: Option<String>,
+    // the `heos://...` command as it was sent.
+    #[serde(default)]
+    pub command: Option<String>,
+    // only sent with a system error, e.g. `-9`.
+    #[serde(default)]
+    pub syserrno: Option<i32>,
+    // the `SEQUENCE` echoed from the failed command.
+    #[serde(default)]
+    pub sequence: Option<u64>,
+}
+
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let command = self.command.as_ref().or(self.context.as_ref());
+        if let Some(command) = command {
+            write!(f, "{} failed: ", command)?;
+        }
+        write!(f, "{}", self.eid)?;
+        if !self.text.is_empty() {
+            write!(f, ", {}", self.text)?;
+        }
+        if let Some(syserrno) = self.syserrno {
+            write!(f, ", syserrno {}", syserrno)?;
+        }
+        Ok(())
+    }
 }
 
 #[derive(Error, Debug)]
@@ -41,6 +129,109 @@ pub enum HeosError {
     NoDevicesFound,
 
     // An invalid command was send to the heos box
-    #[error("Invalid command ")]
+    #[error("{0}")]
     InvalidCommand(ErrorMessage),
+
+    // the command is shown without its password.
+    #[error("no answer to {command} within {after:?}")]
+    Timeout { command: String, after: Duration },
+
+    #[error("no {kind} named {name:?}, known are: {}", known.join(", "))]
+    UnknownName {
+        kind: &'static str,
+        name: String,
+        known: Vec<String>,
+    },
+
+    #[error("{name:?} matches more than one {kind}: {}", candidates.join(", "))]
+    AmbiguousName {
+        kind: &'static str,
+        name: String,
+        candidates: Vec<String>,
+    },
+}
+
+impl HeosError {
+    /// True if the device refused the command only for the moment, e.g.
+    /// because it is still processing the previous one.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            HeosError::InvalidCommand(message) => message.eid.is_transient(),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(eid: u8) -> ErrorMessage {
+        ErrorMessage {
+            eid: eid.into(),
+            text: String::new(),
+            context: None,
+            command: None,
+            syserrno: None,
+            sequence: None,
+        }
+    }
+
+    #[test]
+    fn transient_codes() {
+        let transient: Vec<_> = (0..=16)
+            .filter(|eid| HeosErrorCode::from(*eid).is_transient())
+            .collect();
+        assert_eq!(transient, [5, 13]);
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(HeosError::InvalidCommand(message(13)).is_retryable());
+        assert!(HeosError::InvalidCommand(message(5)).is_retryable());
+        assert!(!HeosError::InvalidCommand(message(2)).is_retryable());
+        let timeout = HeosError::Timeout {
+            command: "heos://system/heart_beat".to_owned(),
+            after: Duration::from_secs(10),
+        };
+        assert!(!timeout.is_retryable());
+        assert!(!HeosError::NoDevicesFound.is_retryable());
+    }
+
+    #[test]
+    fn error_codes_show_their_eid() {
+        assert_eq!(HeosErrorCode::InvalidId.to_string(), "invalid id (eid 2)");
+        assert_eq!(
+            HeosErrorCode::from(13).to_string(),
+            "processing previous command (eid 13)"
+        );
+        assert_eq!(HeosErrorCode::from(99).to_string(), "unknown error");
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(message(2).to_string(), "invalid id (eid 2)");
+
+        let mut failed = message(2);
+        failed.text = "ID Not Valid".to_owned();
+        failed.context = Some("player/get_volume".to_owned());
+        assert_eq!(
+            failed.to_string(),
+            "player/get_volume failed: invalid id (eid 2), ID Not Valid"
+        );
+        // the command as sent is preferred over the name.
+        failed.command = Some("heos://player/get_volume?pid=9".to_owned());
+        assert_eq!(
+            failed.to_string(),
+            "heos://player/get_volume?pid=9 failed: invalid id (eid 2), ID Not Valid"
+        );
+
+        let mut system = message(12);
+        system.text = "System error".to_owned();
+        system.syserrno = Some(-9);
+        assert_eq!(
+            system.to_string(),
+            "system error (eid 12), System error, syserrno -9"
+        );
+    }
 }
@@ -0,0 +1,307 @@
+use serde_json::{Map, Value as Json};
+use tracing::warn;
+
+use crate::{GroupId, Level, Milliseconds, OnOrOff, PlayState, PlayerId, Repeat};
+
+/// The change events a HEOS device sends after
+/// `system/register_for_change_events?enable=on`.
+///
+/// Events this crate doesn't know, or can't make sense of, end up in
+/// `Other` with the message as it was parsed.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HeosEvent {
+    SourcesChanged,
+    PlayersChanged,
+    GroupsChanged,
+    PlayerStateChanged {
+        pid: PlayerId,
+        state: PlayState,
+    },
+    PlayerNowPlayingChanged {
+        pid: PlayerId,
+    },
+    PlayerNowPlayingProgress {
+        pid: PlayerId,
+        cur_pos: Milliseconds,
+        duration: Milliseconds,
+    },
+    PlayerPlaybackError {
+        pid: PlayerId,
+        error: String,
+    },
+    PlayerQueueChanged {
+        pid: PlayerId,
+    },
+    PlayerVolumeChanged {
+        pid: PlayerId,
+        level: Level,
+        mute: OnOrOff,
+    },
+    RepeatModeChanged {
+        pid: PlayerId,
+        repeat: Repeat,
+    },
+    ShuffleModeChanged {
+        pid: PlayerId,
+        shuffle: OnOrOff,
+    },
+    GroupVolumeChanged {
+        gid: GroupId,
+        level: Level,
+        mute: OnOrOff,
+    },
+    // `un` is only sent if a user signed in.
+    UserChanged {
+        un: Option<String>,
+    },
+    // sent by `HeosClient` after the connection was lost and restored,
+    // anything cached may be stale.
+    #[serde(skip_deserializing)]
+    Reconnected,
+    #[serde(skip_deserializing)]
+    Other {
+        event_name: String,
+        message: Json,
+    },
+}
+
+impl HeosEvent {
+    /// Builds the event from its name, e.g. `event/player_state_changed`,
+    /// and the parsed message.
+    pub fn parse(event_name: &str, message: Json) -> HeosEvent {
+        let mut fields = match &message {
+            Json::Object(fields) => fields.clone(),
+            Json::Null => Map::new(),
+            _ => return HeosEvent::other(event_name, message),
+        };
+        let name = event_name.trim_start_matches("event/");
+        fields.insert("event".to_owned(), Json::String(name.to_owned()));
+        match serde_json::from_value(Json::Object(fields)) {
+            Ok(event) => event,
+            Err(err) => {
+                if KNOWN_EVENTS.contains(&name) {
+                    warn!("could not parse {} ({}): {}", event_name, message, err);
+                }
+                HeosEvent::other(event_name, message)
+            }
+        }
+    }
+
+    fn other(event_name: &str, message: Json) -> HeosEvent {
+        HeosEvent::Other {
+            event_name: event_name.to_owned(),
+            message,
+        }
+    }
+
+    /// The name of the event without the `event/` prefix, e.g.
+    /// `player_volume_changed`.
+    pub fn name(&self) -> &str {
+        match self {
+            HeosEvent::SourcesChanged => "sources_changed",
+            HeosEvent::PlayersChanged => "players_changed",
+            HeosEvent::GroupsChanged => "groups_changed",
+            HeosEvent::PlayerStateChanged { .. } => "player_state_changed",
+            HeosEvent::PlayerNowPlayingChanged { .. } => "player_now_playing_changed",
+            HeosEvent::PlayerNowPlayingProgress { .. } => "player_now_playing_progress",
+            HeosEvent::PlayerPlaybackError { .. } => "player_playback_error",
+            HeosEvent::PlayerQueueChanged { .. } => "player_queue_changed",
+            HeosEvent::PlayerVolumeChanged { .. } => "player_volume_changed",
+            HeosEvent::RepeatModeChanged { .. } => "repeat_mode_changed",
+            HeosEvent::ShuffleModeChanged { .. } => "shuffle_mode_changed",
+            HeosEvent::GroupVolumeChanged { .. } => "group_volume_changed",
+            HeosEvent::UserChanged { .. } => "user_changed",
+            HeosEvent::Reconnected => "reconnected",
+            HeosEvent::Other { event_name, .. } => event_name.trim_start_matches("event/"),
+        }
+    }
+
+    /// The player this event is about, if any.
+    pub fn pid(&self) -> Option<PlayerId> {
+        match self {
+            HeosEvent::PlayerStateChanged { pid, .. }
+            | HeosEvent::PlayerNowPlayingChanged { pid }
+            | HeosEvent::PlayerNowPlayingProgress { pid, .. }
+            | HeosEvent::PlayerPlaybackError { pid, .. }
+            | HeosEvent::PlayerQueueChanged { pid }
+            | HeosEvent::PlayerVolumeChanged { pid, .. }
+            | HeosEvent::RepeatModeChanged { pid, .. }
+            | HeosEvent::ShuffleModeChanged { pid, .. } => Some(*pid),
+            _ => None,
+        }
+    }
+
+    /// The group this event is about, if any.
+    pub fn gid(&self) -> Option<GroupId> {
+        match self {
+            HeosEvent::GroupVolumeChanged { gid, .. } => Some(*gid),
+            _ => None,
+        }
+    }
+}
+
+const KNOWN_EVENTS: [&str; 13] = [
+    "sources_changed",
+    "players_changed",
+    "groups_changed",
+    "player_state_changed",
+    "player_now_playing_changed",
+    "player_now_playing_progress",
+    "player_playback_error",
+    "player_queue_changed",
+    "player_volume_changed",
+    "repeat_mode_changed",
+    "shuffle_mode_changed",
+    "group_volume_changed",
+    "user_changed",
+];
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn known_events() {
+        let cases = [
+            ("sources_changed", Json::Null, HeosEvent::SourcesChanged),
+            ("players_changed", Json::Null, HeosEvent::PlayersChanged),
+            ("groups_changed", Json::Null, HeosEvent::GroupsChanged),
+            (
+                "player_state_changed",
+                json!({ "pid": 1, "state": "play" }),
+                HeosEvent::PlayerStateChanged {
+                    pid: 1,
+                    state: PlayState::Play,
+                },
+            ),
+            (
+                "player_now_playing_changed",
+                json!({ "pid": 1 }),
+                HeosEvent::PlayerNowPlayingChanged { pid: 1 },
+            ),
+            (
+                "player_now_playing_progress",
+                json!({ "pid": 1, "cur_pos": 5000, "duration": 180000 }),
+                HeosEvent::PlayerNowPlayingProgress {
+                    pid: 1,
+                    cur_pos: 5000,
+                    duration: 180000,
+                },
+            ),
+            (
+                "player_playback_error",
+                json!({ "pid": 1, "error": "Could not play" }),
+                HeosEvent::PlayerPlaybackError {
+                    pid: 1,
+                    error: "Could not play".to_owned(),
+                },
+            ),
+            (
+                "player_queue_changed",
+                json!({ "pid": 1 }),
+                HeosEvent::PlayerQueueChanged { pid: 1 },
+            ),
+            (
+                "player_volume_changed",
+                json!({ "pid": 1, "level": 40, "mute": "off" }),
+                HeosEvent::PlayerVolumeChanged {
+                    pid: 1,
+                    level: 40,
+                    mute: OnOrOff::Off,
+                },
+            ),
+            (
+                "repeat_mode_changed",
+                json!({ "pid": 1, "repeat": "on_all" }),
+                HeosEvent::RepeatModeChanged {
+                    pid: 1,
+                    repeat: Repeat::OnAll,
+                },
+            ),
+            (
+                "shuffle_mode_changed",
+                json!({ "pid": 1, "shuffle": "on" }),
+                HeosEvent::ShuffleModeChanged {
+                    pid: 1,
+                    shuffle: OnOrOff::On,
+                },
+            ),
+            (
+                "group_volume_changed",
+                json!({ "gid": -5, "level": 20, "mute": "on" }),
+                HeosEvent::GroupVolumeChanged {
+                    gid: -5,
+                    level: 20,
+                    mute: OnOrOff::On,
+                },
+            ),
+            (
+                "user_changed",
+                json!({ "signed_in": true, "un": "heos@example.com" }),
+                HeosEvent::UserChanged {
+                    un: Some("heos@example.com".to_owned()),
+                },
+            ),
+            (
+                "user_changed",
+                json!({ "signed_out": true }),
+                HeosEvent::UserChanged { un: None },
+            ),
+        ];
+        // every known event has a sample above.
+        for known in KNOWN_EVENTS {
+            assert!(cases.iter().any(|(name, _, _)| *name == known), "{}", known);
+        }
+        for (name, message, expected) in cases {
+            let event = HeosEvent::parse(&format!("event/{}", name), message);
+            assert_eq!(event, expected);
+            assert_eq!(event.name(), name);
+        }
+    }
+
+    #[test]
+    fn unknown_events() {
+        let message = json!({ "pid": 1 });
+        let event = HeosEvent::parse("event/foo", message.clone());
+        assert_eq!(
+            event,
+            HeosEvent::Other {
+                event_name: "event/foo".to_owned(),
+                message,
+            }
+        );
+        assert_eq!(event.name(), "foo");
+    }
+
+    #[test]
+    fn known_events_with_bad_fields() {
+        let cases = [
+            ("player_state_changed", Json::Null),
+            ("player_state_changed", json!({ "pid": 1 })),
+            (
+                "player_volume_changed",
+                json!({ "pid": 1, "level": "loud", "mute": "off" }),
+            ),
+            (
+                "repeat_mode_changed",
+                json!({ "pid": 1, "repeat": "sometimes" }),
+            ),
+            ("group_volume_changed", json!({ "level": 20, "mute": "on" })),
+            ("player_queue_changed", json!("pid=1")),
+        ];
+        for (name, message) in cases {
+            let event_name = format!("event/{}", name);
+            let event = HeosEvent::parse(&event_name, message.clone());
+            assert_eq!(
+                event,
+                HeosEvent::Other {
+                    event_name,
+                    message
+                }
+            );
+        }
+    }
+}
@@ -8,9 +8,18 @@ extern crate serde_qs as qs;
 use crate::error::HeosError;
 
 mod connection;
+pub mod discovery;
 pub mod error;
+mod event;
+// also compiled for the crate's own tests, enable `test-support` to use it
+// from other crates.
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+pub mod resolve;
+pub mod state;
 mod types;
 pub use connection::*;
+pub use event::*;
 pub use types::*;
 
 pub type HeosResult<T> = Result<T, HeosError>;
@@ -1,47 +1,38 @@
-use heos_daemon_rust::{Connection, Frame, HeosResult, PlayerCommand, SystemCommand};
+use heos_daemon_rust::discovery::{self, HEOS_PORT};
+use heos_daemon_rust::{HeosClient, HeosResult};
 use pretty_env_logger::env_logger;
-use serde_json::{to_string_pretty, to_value};
-use heos_daemon_rust::OnOrOff::On;
+use std::env;
+use std::time::Duration;
+use tracing::warn;
 
-#[tokio::main(flavor = "multi_thread", worker_threads = 10)]
-async fn main() -> HeosResult<()> {
-    env_logger::init_from_env(env_logger::Env::new().default_filter_or("debug"));
-    let mut connection = Connection::connect("192.168.178.35:1255").await?;
-
-    let res = connection
-        .execute_command(PlayerCommand::GetPlayers)
-        .await?;
-    let json = to_value(&res).unwrap();
-    println!("{}", serde_json::to_string_pretty(&json).unwrap());
+mod api;
+mod credentials;
 
-    let res = connection
-        .execute_command(PlayerCommand::GetPlayerVolume { pid: 1128532863 })
-        .await?;
-    let json = to_value(&res).unwrap();
-    println!("{}", serde_json::to_string_pretty(&json).unwrap());
+// where the http api listens unless `HEOS_DAEMON_LISTEN` says otherwise.
+const DEFAULT_LISTEN: &str = "0.0.0.0:8080";
 
-    let res = connection
-        .execute_command(PlayerCommand::GetPlayState { pid: 1128532863 })
-        .await?;
-    let json = to_value(&res).unwrap();
-    println!("{}", serde_json::to_string_pretty(&json).unwrap());
-
-    // println!("errrrrror!");
-    // let res = connection
-    //     .execute_command(PlayerCommand::GetPlayState { pid: 11283 })
-    //     .await?;
-    // let json = to_value(&res).unwrap();
-    // println!("{}", serde_json::to_string_pretty(&json).unwrap());
+#[tokio::main(flavor = "multi_thread", worker_threads = 10)]
+async fn main() -> HeosResult<()> {
+    env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
+    let client = connect().await?;
+    if let Some(credentials) = credentials::Credentials::load()? {
+        // the api is still useful without an account.
+        if let Err(err) = credentials.sign_in(&client).await {
+            warn!("could not sign in: {}", err);
+        }
+    }
+    let listen = env::var("HEOS_DAEMON_LISTEN").unwrap_or_else(|_| DEFAULT_LISTEN.to_owned());
+    api::serve(listen, client).await
+}
 
-    let _ = connection.execute_command(SystemCommand::RegisterForChangeEvents { enable: On}).await?;
-    loop {
-        let response = connection.read_frame().await?;
-        match response {
-            None => {}
-            Some(frame) => {
-                println!("{:?}", frame);
-            }
+// connects to `HEOS_HOST` if set, to the first discovered device otherwise.
+async fn connect() -> HeosResult<HeosClient> {
+    match env::var("HEOS_HOST") {
+        Ok(host) if host.contains(':') => HeosClient::connect(host).await,
+        Ok(host) => HeosClient::connect((host.as_str(), HEOS_PORT)).await,
+        Err(_) => {
+            let device = discovery::discover(Duration::from_secs(5)).await?;
+            HeosClient::connect(device.heos_address()).await
         }
     }
-    Ok(())
 }
@@ -0,0 +1,373 @@
+//! A scriptable fake HEOS device for tests.
+//!
+//! `MockHeos` listens on a loopback port and speaks the CLI protocol: it
+//! reads `heos://...\r\n` commands, answers them with JSON lines and can
+//! push events to every connected client at any time.
+//!
+//! ```no_run
+//! # async fn example() -> heos_daemon_rust::HeosResult<()> {
+//! use heos_daemon_rust::mock::{MockHeos, MockReply};
+//! use heos_daemon_rust::{GetPlayerVolume, HeosClient};
+//!
+//! let mock = MockHeos::start().await?;
+//! mock.on("player/get_volume", MockReply::message("level=42"));
+//! let client = HeosClient::connect(mock.address()).await?;
+//! assert_eq!(client.execute_command(GetPlayerVolume { pid: 1 }).await?, 42);
+//! mock.send_event("player_volume_changed", "pid=1&level=43&mute=off");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::{json, Value as Json};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::HeosResult;
+
+/// How the mock answers a command.
+#[derive(Clone, Debug)]
+pub enum MockReply {
+    /// Answers with `result: success`. The command's parameters are echoed
+    /// in the message like a real device does, followed by `message`.
+    Success { message: String, payload: Json },
+    /// Answers with `result: fail` and `eid=..&text=..` in the message,
+    /// plus `syserrno=..` if set.
+    Failure {
+        eid: u8,
+        text: String,
+        syserrno: Option<i32>,
+    },
+    /// Sends `command under process` first, then the actual reply.
+    UnderProcess(Box<MockReply>),
+    /// Answers after a while; events and other commands are sent meanwhile.
+    Delayed(Duration, Box<MockReply>),
+    /// Never answers.
+    Silent,
+}
+
+impl MockReply {
+    pub fn success() -> MockReply {
+        MockReply::message("")
+    }
+
+    pub fn message(message: &str) -> MockReply {
+        MockReply::Success {
+            message: message.to_owned(),
+            payload: Json::Null,
+        }
+    }
+
+    pub fn payload(payload: Json) -> MockReply {
+        MockReply::Success {
+            message: String::new(),
+            payload,
+        }
+    }
+
+    pub fn failure(eid: u8, text: &str) -> MockReply {
+        MockReply::Failure {
+            eid,
+            text: text.to_owned(),
+            syserrno: None,
+        }
+    }
+
+    /// Adds `syserrno` to a failure, as sent with system errors (eid 12).
+    pub fn with_syserrno(self, syserrno: i32) -> MockReply {
+        match self {
+            MockReply::Failure { eid, text, .. } => MockReply::Failure {
+                eid,
+                text,
+                syserrno: Some(syserrno),
+            },
+            reply => reply,
+        }
+    }
+
+    pub fn under_process(self) -> MockReply {
+        MockReply::UnderProcess(Box::new(self))
+    }
+
+    pub fn after(self, delay: Duration) -> MockReply {
+        MockReply::Delayed(delay, Box::new(self))
+    }
+}
+
+#[derive(Default)]
+struct Script {
+    once: HashMap<String, VecDeque<MockReply>>,
+    always: HashMap<String, MockReply>,
+    received: Vec<String>,
+}
+
+impl Script {
+    fn reply_for(&mut self, command: &str) -> MockReply {
+        self.received.push(command.to_owned());
+        let name = command.split('?').next().unwrap_or_default();
+        if let Some(reply) = self.once.get_mut(name).and_then(VecDeque::pop_front) {
+            return reply;
+        }
+        match self.always.get(name) {
+            Some(reply) => reply.clone(),
+            // everything not scripted simply succeeds.
+            None => MockReply::success(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Broadcast {
+    Line(String),
+    Disconnect,
+}
+
+/// A fake HEOS device listening on a loopback port.
+///
+/// Unscripted commands succeed with their parameters echoed; the common
+/// getters answer with a small living room and kitchen setup.
+#[derive(Clone)]
+pub struct MockHeos {
+    address: SocketAddr,
+    script: Arc<Mutex<Script>>,
+    broadcast: broadcast::Sender<Broadcast>,
+}
+
+impl MockHeos {
+    pub async fn start() -> HeosResult<MockHeos> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let (broadcast, _) = broadcast::channel(64);
+        let mock = MockHeos {
+            address,
+            script: Arc::new(Mutex::new(Script::default())),
+            broadcast,
+        };
+        mock.script_defaults();
+        let accepting = mock.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, peer)) = listener.accept().await {
+                debug!("mock heos accepted {}", peer);
+                tokio::spawn(accepting.clone().serve(socket));
+            }
+        });
+        Ok(mock)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Answers every following `command` (e.g. `player/get_volume`) with `reply`.
+    pub fn on(&self, command: &str, reply: MockReply) {
+        let mut script = self.script.lock().unwrap();
+        script.always.insert(command.to_owned(), reply);
+    }
+
+    /// Answers the next `command` with `reply`, before any `on` reply.
+    pub fn once(&self, command: &str, reply: MockReply) {
+        let mut script = self.script.lock().unwrap();
+        script
+            .once
+            .entry(command.to_owned())
+            .or_default()
+            .push_back(reply);
+    }
+
+    /// Sends `event/<event>` with `message` to every connected client.
+    pub fn send_event(&self, event: &str, message: &str) {
+        let line = json!({
+            "heos": {
+                "command": format!("event/{}", event),
+                "message": message,
+            }
+        });
+        let _ = self.broadcast.send(Broadcast::Line(line.to_string()));
+    }
+
+    /// Closes every open connection, like a rebooting device would.
+    pub fn disconnect_all(&self) {
+        let _ = self.broadcast.send(Broadcast::Disconnect);
+    }
+
+    /// All commands received so far, without the `heos://` prefix.
+    pub fn received(&self) -> Vec<String> {
+        self.script.lock().unwrap().received.clone()
+    }
+
+    fn script_defaults(&self) {
+        let players = json!([
+            {
+                "name": "Living Room",
+                "pid": 1,
+                "model": "HEOS 7",
+                "version": "1.520.200",
+                "ip": "127.0.0.1",
+                "network": "wired",
+                "lineout": 0,
+                "serial": "AAA0000001"
+            },
+            {
+                "name": "Kitchen",
+                "pid": 2,
+                "model": "HEOS 1",
+                "version": "1.520.200",
+                "ip": "127.0.0.1",
+                "network": "wifi",
+                "lineout": 0,
+                "serial": "AAA0000002"
+            }
+        ]);
+        self.on("player/get_players", MockReply::payload(players));
+        self.on("group/get_groups", MockReply::payload(json!([])));
+        self.on("player/get_queue", MockReply::payload(json!([])));
+        self.on("player/get_volume", MockReply::message("level=25"));
+        self.on("group/get_volume", MockReply::message("level=25"));
+        self.on("player/get_mute", MockReply::message("state=off"));
+        self.on("group/get_mute", MockReply::message("state=off"));
+        self.on("player/get_play_state", MockReply::message("state=stop"));
+        self.on(
+            "player/get_play_mode",
+            MockReply::message("repeat=off&shuffle=off"),
+        );
+        self.on(
+            "player/get_now_playing_media",
+            MockReply::payload(json!({
+                "type": "song",
+                "song": "Song",
+                "album": "Album",
+                "artist": "Artist",
+                "image_url": "",
+                "album_id": "1",
+                "mid": "1",
+                "qid": 1,
+                "sid": 1024
+            })),
+        );
+        self.on("system/check_account", MockReply::message("signed_out"));
+    }
+
+    async fn serve(self, socket: TcpStream) {
+        let mut broadcast = self.broadcast.subscribe();
+        let (reader, mut writer) = socket.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        // delayed answers, written once they are due.
+        let (later, mut due) = mpsc::unbounded_channel::<Vec<String>>();
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let line = match line {
+                        Ok(Some(line)) => line,
+                        _ => break,
+                    };
+                    let command = match line.trim().strip_prefix("heos://") {
+                        Some(command) => command.to_owned(),
+                        None => continue,
+                    };
+                    let reply = self.script.lock().unwrap().reply_for(&command);
+                    let mut answer = Vec::new();
+                    if let MockReply::Delayed(delay, reply) = reply {
+                        render(&command, *reply, &mut answer);
+                        let later = later.clone();
+                        tokio::spawn(async move {
+                            tokio::time::sleep(delay).await;
+                            let _ = later.send(answer);
+                        });
+                        continue;
+                    }
+                    render(&command, reply, &mut answer);
+                    for line in answer {
+                        if write_line(&mut writer, &line).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Some(answer) = due.recv() => {
+                    for line in answer {
+                        if write_line(&mut writer, &line).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                message = broadcast.recv() => match message {
+                    Ok(Broadcast::Line(line)) => {
+                        if write_line(&mut writer, &line).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {}
+                    Ok(Broadcast::Disconnect) | Err(RecvError::Closed) => break,
+                },
+            }
+        }
+        debug!("mock heos closed a connection");
+    }
+}
+
+async fn write_line<W: AsyncWriteExt + Unpin>(writer: &mut W, line: &str) -> std::io::Result<()> {
+    writer.write_all(format!("{}\r\n", line).as_bytes()).await
+}
+
+// turns a reply into the lines sent for it.
+fn render(command: &str, reply: MockReply, lines: &mut Vec<String>) {
+    let (name, params) = command.split_once('?').unwrap_or((command, ""));
+    let join = |first: &str, second: &str| match (first.is_empty(), second.is_empty()) {
+        (true, _) => second.to_owned(),
+        (_, true) => first.to_owned(),
+        _ => format!("{}&{}", first, second),
+    };
+    match reply {
+        MockReply::Success { message, payload } => {
+            let mut response = json!({
+                "heos": {
+                    "command": name,
+                    "result": "success",
+                    "message": join(params, &message),
+                }
+            });
+            if !payload.is_null() {
+                response["payload"] = payload;
+            }
+            lines.push(response.to_string());
+        }
+        MockReply::Failure {
+            eid,
+            text,
+            syserrno,
+        } => {
+            let mut failure = format!("eid={}&text={}", eid, text);
+            if let Some(syserrno) = syserrno {
+                failure = format!("{}&syserrno={}", failure, syserrno);
+            }
+            let response = json!({
+                "heos": {
+                    "command": name,
+                    "result": "fail",
+                    "message": join(&failure, params),
+                }
+            });
+            lines.push(response.to_string());
+        }
+        MockReply::UnderProcess(reply) => {
+            let response = json!({
+                "heos": {
+                    "command": name,
+                    "result": "success",
+                    "message": "command under process",
+                }
+            });
+            lines.push(response.to_string());
+            render(command, *reply, lines);
+        }
+        // only a reply as a whole can be delayed.
+        MockReply::Delayed(_, reply) => render(command, *reply, lines),
+        MockReply::Silent => {}
+    }
+}
@@ -0,0 +1,306 @@
+//! Looking up players and groups by name.
+//!
+//! Names are matched case-insensitively and ignoring everything but letters
+//! and digits, so `living-room` finds `Living Room`. An exact match wins,
+//! otherwise a unique prefix and finally a unique part of a name is
+//! accepted. A plain number is taken as the id itself.
+
+use std::sync::{Arc, Mutex, Weak};
+
+use tokio_stream::StreamExt;
+use tracing::debug;
+
+use crate::error::HeosError;
+use crate::{GetGroups, GetPlayers, GroupId, HeosClient, HeosEvent, HeosResult, PlayerId};
+
+/// Resolves player and group names to their ids.
+///
+/// The names are fetched on first use and cached until a `players_changed`,
+/// `groups_changed` or reconnect event invalidates them.
+#[derive(Clone)]
+pub struct Resolver {
+    client: HeosClient,
+    cache: Arc<Mutex<Cache>>,
+}
+
+#[derive(Default)]
+struct Cache {
+    players: Names<PlayerId>,
+    groups: Names<GroupId>,
+}
+
+struct Names<T> {
+    entries: Option<Vec<(String, T)>>,
+    // bumped on every invalidation so a fetch racing with one isn't cached.
+    generation: u64,
+}
+
+impl<T> Default for Names<T> {
+    fn default() -> Self {
+        Names {
+            entries: None,
+            generation: 0,
+        }
+    }
+}
+
+impl<T> Names<T> {
+    fn invalidate(&mut self) {
+        self.entries = None;
+        self.generation += 1;
+    }
+}
+
+impl Resolver {
+    pub fn new(client: HeosClient) -> Resolver {
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        let events = client.events();
+        let weak = Arc::downgrade(&cache);
+        tokio::spawn(invalidate_on_change(events, weak));
+        Resolver { client, cache }
+    }
+
+    pub async fn player(&self, name: &str) -> HeosResult<PlayerId> {
+        if let Ok(pid) = name.trim().parse() {
+            return Ok(pid);
+        }
+        let players = self.players().await?;
+        find("player", name, &players)
+    }
+
+    pub async fn group(&self, name: &str) -> HeosResult<GroupId> {
+        if let Ok(gid) = name.trim().parse() {
+            return Ok(gid);
+        }
+        let groups = self.groups().await?;
+        find("group", name, &groups)
+    }
+
+    /// Drops the cached names, they are fetched again on the next lookup.
+    pub fn invalidate(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.players.invalidate();
+        cache.groups.invalidate();
+    }
+
+    async fn players(&self) -> HeosResult<Vec<(String, PlayerId)>> {
+        let generation = {
+            let cache = self.cache.lock().unwrap();
+            if let Some(entries) = &cache.players.entries {
+                return Ok(entries.clone());
+            }
+            cache.players.generation
+        };
+        let players = self.client.execute_command(GetPlayers).await?;
+        let entries: Vec<_> = players.into_iter().map(|p| (p.name, p.pid)).collect();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.players.generation == generation {
+            cache.players.entries = Some(entries.clone());
+        }
+        Ok(entries)
+    }
+
+    async fn groups(&self) -> HeosResult<Vec<(String, GroupId)>> {
+        let generation = {
+            let cache = self.cache.lock().unwrap();
+            if let Some(entries) = &cache.groups.entries {
+                return Ok(entries.clone());
+            }
+            cache.groups.generation
+        };
+        let groups = self.client.execute_command(GetGroups).await?;
+        let entries: Vec<_> = groups.into_iter().map(|g| (g.name, g.gid)).collect();
+        let mut cache = self.cache.lock().unwrap();
+        if cache.groups.generation == generation {
+            cache.groups.entries = Some(entries.clone());
+        }
+        Ok(entries)
+    }
+}
+
+async fn invalidate_on_change(
+    events: impl tokio_stream::Stream<Item = HeosEvent>,
+    cache: Weak<Mutex<Cache>>,
+) {
+    let mut events = Box::pin(events);
+    while let Some(event) = events.next().await {
+        // stop once every resolver is gone.
+        let cache = match cache.upgrade() {
+            Some(cache) => cache,
+            None => break,
+        };
+        let mut cache = cache.lock().unwrap();
+        match event {
+            HeosEvent::PlayersChanged => cache.players.invalidate(),
+            HeosEvent::GroupsChanged => cache.groups.invalidate(),
+            HeosEvent::Reconnected => {
+                cache.players.invalidate();
+                cache.groups.invalidate();
+            }
+            _ => {}
+        }
+    }
+    debug!("stopped watching for name changes");
+}
+
+// lower case letters and digits only.
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn find<T: Copy>(kind: &'static str, name: &str, entries: &[(String, T)]) -> HeosResult<T> {
+    let wanted = normalize(name);
+    let exact = |candidate: &str| candidate == wanted;
+    let prefix = |candidate: &str| candidate.starts_with(&wanted);
+    let part = |candidate: &str| candidate.contains(&wanted);
+    let matchers: [&dyn Fn(&str) -> bool; 3] = [&exact, &prefix, &part];
+    if !wanted.is_empty() {
+        for matches in matchers {
+            let found: Vec<_> = entries
+                .iter()
+                .filter(|(candidate, _)| matches(&normalize(candidate)))
+                .collect();
+            match found.as_slice() {
+                [] => continue,
+                [(_, id)] => return Ok(*id),
+                _ => {
+                    return Err(HeosError::AmbiguousName {
+                        kind,
+                        name: name.to_owned(),
+                        candidates: found.iter().map(|(name, _)| name.clone()).collect(),
+                    })
+                }
+            }
+        }
+    }
+    Err(HeosError::UnknownName {
+        kind,
+        name: name.to_owned(),
+        known: entries.iter().map(|(name, _)| name.clone()).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock::{MockHeos, MockReply};
+    use crate::{Backoff, ClientOptions};
+
+    fn names(names: &[&str]) -> Vec<(String, u32)> {
+        (1..)
+            .zip(names)
+            .map(|(id, name)| (name.to_string(), id))
+            .collect()
+    }
+
+    #[test]
+    fn exact_beats_prefix() {
+        let entries = names(&["Kitchen Left", "Kitchen"]);
+        assert_eq!(find("player", "kitchen", &entries).unwrap(), 2);
+    }
+
+    #[test]
+    fn prefix_beats_part() {
+        let entries = names(&["Upstairs Bath", "Bathroom"]);
+        assert_eq!(find("player", "bath", &entries).unwrap(), 2);
+        assert_eq!(find("player", "stairs", &entries).unwrap(), 1);
+    }
+
+    #[test]
+    fn case_and_whitespace_are_ignored() {
+        let entries = names(&["Living Room", "Kitchen"]);
+        assert_eq!(find("player", "living-room", &entries).unwrap(), 1);
+        assert_eq!(find("player", "  LIVINGROOM ", &entries).unwrap(), 1);
+    }
+
+    #[test]
+    fn two_prefixes_are_ambiguous() {
+        let entries = names(&["Kitchen Left", "Kitchen Right"]);
+        match find("player", "kitchen", &entries) {
+            Err(HeosError::AmbiguousName { candidates, .. }) => {
+                assert_eq!(candidates, ["Kitchen Left", "Kitchen Right"])
+            }
+            other => panic!("expected an ambiguous name, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn unknown_names() {
+        let entries = names(&["Living Room", "Kitchen"]);
+        for name in ["garage", "", " - "] {
+            match find("player", name, &entries) {
+                Err(HeosError::UnknownName { known, .. }) => {
+                    assert_eq!(known, ["Living Room", "Kitchen"])
+                }
+                other => panic!("expected an unknown name, got {:?}", other),
+            }
+        }
+    }
+
+    fn players(names: &[(&str, PlayerId)]) -> MockReply {
+        let players: Vec<_> = names
+            .iter()
+            .map(|(name, pid)| json!({ "name": name, "pid": pid, "model": "HEOS 1" }))
+            .collect();
+        MockReply::payload(json!(players))
+    }
+
+    // asks again until the cache was dropped and `name` has moved to `pid`.
+    async fn until_resolved(resolver: &Resolver, name: &str, pid: PlayerId) {
+        let resolved = async {
+            while resolver.player(name).await.ok() != Some(pid) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), resolved)
+            .await
+            .expect("the cache wasn't invalidated in time");
+    }
+
+    #[tokio::test]
+    async fn changes_invalidate_the_cache() {
+        let mock = MockHeos::start().await.unwrap();
+        let options = ClientOptions {
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(50),
+            },
+            ..ClientOptions::default()
+        };
+        let client = HeosClient::connect_with(mock.address(), options)
+            .await
+            .unwrap();
+        let resolver = Resolver::new(client);
+        assert_eq!(resolver.player("kitchen").await.unwrap(), 2);
+
+        // the cached names are used until the device reports a change.
+        mock.on("player/get_players", players(&[("Kitchen", 3)]));
+        assert_eq!(resolver.player("kitchen").await.unwrap(), 2);
+        mock.send_event("players_changed", "");
+        until_resolved(&resolver, "kitchen", 3).await;
+
+        mock.on("player/get_players", players(&[("Kitchen", 4)]));
+        mock.disconnect_all();
+        until_resolved(&resolver, "kitchen", 4).await;
+
+        assert!(resolver.group("upstairs").await.is_err());
+        let groups = json!([{ "name": "Upstairs", "gid": 5, "players": [] }]);
+        mock.on("group/get_groups", MockReply::payload(groups));
+        mock.send_event("groups_changed", "");
+        let resolved = async {
+            while resolver.group("upstairs").await.ok() != Some(5) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), resolved)
+            .await
+            .expect("the cache wasn't invalidated in time");
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::{mpsc, watch};
+use tokio_stream::StreamExt;
+use tracing::{debug, warn};
+
+use crate::{
+    GetGroupMute, GetGroupVolume, GetGroups, GetNowPlayingMedia, GetPlayMode, GetPlayState,
+    GetPlayerMute, GetPlayerVolume, GetPlayers, GroupId, GroupInfo, HeosClient, HeosEvent,
+    HeosResult, Level, Milliseconds, NowPlaying, OnOrOff, PlayMode, PlayState, PlayerId,
+    PlayerInfo,
+};
+
+/// Everything known about a player. The optional parts are `None` until
+/// they could be fetched.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct PlayerState {
+    pub info: PlayerInfo,
+    pub volume: Option<Level>,
+    pub mute: Option<OnOrOff>,
+    pub play_state: Option<PlayState>,
+    pub play_mode: Option<PlayMode>,
+    pub now_playing: Option<NowPlaying>,
+    pub progress: Option<Progress>,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub struct Progress {
+    pub cur_pos: Milliseconds,
+    pub duration: Milliseconds,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct GroupState {
+    pub info: GroupInfo,
+    pub volume: Option<Level>,
+    pub mute: Option<OnOrOff>,
+}
+
+/// The state of all players and groups at one point in time.
+#[derive(Clone, Debug, Default, Serialize, PartialEq)]
+pub struct Snapshot {
+    pub players: BTreeMap<PlayerId, PlayerState>,
+    pub groups: BTreeMap<GroupId, GroupState>,
+}
+
+/// An in-memory model of the HEOS system kept current from change events.
+///
+/// The state is fetched completely on start and after every reconnect;
+/// afterwards events either update it directly or, where the event doesn't
+/// carry the new value (e.g. `player_now_playing_changed`), trigger a
+/// fetch of the affected part. Fetches run in the background, so events
+/// keep being applied while one is running, and again on top of its result.
+#[derive(Clone, Debug)]
+pub struct HeosState {
+    snapshot: watch::Receiver<Snapshot>,
+}
+
+impl HeosState {
+    /// Fetches the current state and keeps it up to date for as long as
+    /// the client is connected.
+    pub async fn start(client: HeosClient) -> HeosResult<HeosState> {
+        // subscribe first so nothing happening during the fetch is lost.
+        let events = client.events();
+        let snapshot = fetch_all(&client).await?;
+        let (sender, receiver) = watch::channel(snapshot);
+        let sender = Arc::new(sender);
+        let pending = Arc::new(Mutex::new(Pending::default()));
+        // holds at most one wakeup, everything wanted meanwhile is merged.
+        let (wake, woken) = mpsc::channel(1);
+        tokio::spawn(refetch(client, sender.clone(), pending.clone(), woken));
+        tokio::spawn(async move {
+            let mut events = Box::pin(events);
+            while let Some(event) = events.next().await {
+                let mut pending = pending.lock().unwrap();
+                let pending = &mut *pending;
+                if let Some(applied) = &mut pending.applied {
+                    applied.push(event.clone());
+                }
+                if apply(&sender, &mut pending.refetch, event) {
+                    let _ = wake.try_send(());
+                }
+            }
+            // dropping `wake` stops the fetching task too.
+            debug!("stopped updating heos state");
+        });
+        Ok(HeosState { snapshot: receiver })
+    }
+
+    /// A copy of the current state.
+    pub fn snapshot(&self) -> Snapshot {
+        self.snapshot.borrow().clone()
+    }
+
+    pub fn player(&self, pid: PlayerId) -> Option<PlayerState> {
+        self.snapshot.borrow().players.get(&pid).cloned()
+    }
+
+    pub fn group(&self, gid: GroupId) -> Option<GroupState> {
+        self.snapshot.borrow().groups.get(&gid).cloned()
+    }
+
+    /// A receiver notified after every change of the state.
+    pub fn subscribe(&self) -> watch::Receiver<Snapshot> {
+        self.snapshot.clone()
+    }
+}
+
+// what has to be fetched again.
+#[derive(Debug, Default)]
+struct Refetch {
+    all: bool,
+    players: bool,
+    groups: bool,
+    now_playing: BTreeSet<PlayerId>,
+    play_modes: BTreeSet<PlayerId>,
+}
+
+// shared by the task applying the events and the one fetching.
+#[derive(Debug, Default)]
+struct Pending {
+    refetch: Refetch,
+    // the events that arrived since the running fetch started, they are
+    // newer than what it returns.
+    applied: Option<Vec<HeosEvent>>,
+}
+
+// applies what the event carries, anything else is added to `refetch`.
+// Returns whether there is something to fetch now.
+fn apply(snapshot: &watch::Sender<Snapshot>, refetch: &mut Refetch, event: HeosEvent) -> bool {
+    match event {
+        HeosEvent::Reconnected => refetch.all = true,
+        HeosEvent::PlayersChanged => refetch.players = true,
+        HeosEvent::GroupsChanged => refetch.groups = true,
+        HeosEvent::PlayerNowPlayingChanged { pid } => {
+            refetch.now_playing.insert(pid);
+        }
+        HeosEvent::PlayerStateChanged { pid, state } => {
+            update_player(snapshot, pid, |player| player.play_state = Some(state))
+        }
+        HeosEvent::PlayerNowPlayingProgress {
+            pid,
+            cur_pos,
+            duration,
+        } => update_player(snapshot, pid, |player| {
+            player.progress = Some(Progress { cur_pos, duration })
+        }),
+        HeosEvent::PlayerVolumeChanged { pid, level, mute } => {
+            update_player(snapshot, pid, |player| {
+                player.volume = Some(level);
+                player.mute = Some(mute);
+            })
+        }
+        HeosEvent::RepeatModeChanged { pid, repeat } => {
+            update_play_mode(snapshot, refetch, pid, |mode| mode.repeat = repeat)
+        }
+        HeosEvent::ShuffleModeChanged { pid, shuffle } => {
+            update_play_mode(snapshot, refetch, pid, |mode| mode.shuffle = shuffle)
+        }
+        HeosEvent::GroupVolumeChanged { gid, level, mute } => {
+            snapshot.send_if_modified(|state| match state.groups.get_mut(&gid) {
+                Some(group) => {
+                    group.volume = Some(level);
+                    group.mute = Some(mute);
+                    true
+                }
+                None => false,
+            });
+        }
+        _ => {}
+    }
+    refetch.all
+        || refetch.players
+        || refetch.groups
+        || !refetch.now_playing.is_empty()
+        || !refetch.play_modes.is_empty()
+}
+
+// fetches whatever is wanted each time it is woken up, until the task
+// applying the events is gone.
+async fn refetch(
+    client: HeosClient,
+    snapshot: Arc<watch::Sender<Snapshot>>,
+    pending: Arc<Mutex<Pending>>,
+    mut woken: mpsc::Receiver<()>,
+) {
+    // set if events applied again after a fetch want more fetched.
+    let mut again = false;
+    while again || woken.recv().await.is_some() {
+        again = false;
+        let refetch = mem::take(&mut pending.lock().unwrap().refetch);
+        if refetch.all {
+            record(&pending);
+            let fetched = fetch_all(&client).await;
+            match replace(&snapshot, &pending, fetched, |state, fetched| {
+                *state = fetched
+            }) {
+                Ok(wanted) => again = wanted,
+                Err(err) => warn!("could not fetch heos state: {}", err),
+            }
+            continue;
+        }
+        if refetch.players {
+            record(&pending);
+            let fetched = fetch_players(&client).await;
+            match replace(&snapshot, &pending, fetched, |state, players| {
+                state.players = players
+            }) {
+                Ok(wanted) => again |= wanted,
+                Err(err) => warn!("could not fetch players: {}", err),
+            }
+        }
+        if refetch.groups {
+            record(&pending);
+            let fetched = fetch_groups(&client).await;
+            match replace(&snapshot, &pending, fetched, |state, groups| {
+                state.groups = groups
+            }) {
+                Ok(wanted) => again |= wanted,
+                Err(err) => warn!("could not fetch groups: {}", err),
+            }
+        }
+        for pid in refetch.now_playing {
+            record(&pending);
+            let fetched = fetch_now_playing(&client, pid).await;
+            match replace(&snapshot, &pending, fetched, |state, now_playing| {
+                if let Some(player) = state.players.get_mut(&pid) {
+                    player.now_playing = Some(now_playing);
+                    player.progress = None;
+                }
+            }) {
+                Ok(wanted) => again |= wanted,
+                Err(err) => warn!("could not fetch now playing media of {}: {}", pid, err),
+            }
+        }
+        for pid in refetch.play_modes {
+            record(&pending);
+            let fetched = client.execute_command(GetPlayMode { pid }).await;
+            match replace(&snapshot, &pending, fetched, |state, play_mode| {
+                if let Some(player) = state.players.get_mut(&pid) {
+                    player.play_mode = Some(play_mode);
+                }
+            }) {
+                Ok(wanted) => again |= wanted,
+                Err(err) => warn!("could not fetch play mode of {}: {}", pid, err),
+            }
+        }
+    }
+    debug!("stopped fetching heos state");
+}
+
+// keeps the events arriving from now on until the fetch is `replace`d.
+fn record(pending: &Mutex<Pending>) {
+    pending.lock().unwrap().applied = Some(Vec::new());
+}
+
+// puts what was fetched into the state, then applies the events recorded
+// during the fetch again so their newer values aren't overwritten. Returns
+// whether there is something to fetch now.
+fn replace<T, F: FnOnce(&mut Snapshot, T)>(
+    snapshot: &watch::Sender<Snapshot>,
+    pending: &Mutex<Pending>,
+    fetched: HeosResult<T>,
+    update: F,
+) -> HeosResult<bool> {
+    let mut pending = pending.lock().unwrap();
+    let applied = pending.applied.take().unwrap_or_default();
+    let fetched = fetched?;
+    snapshot.send_modify(|state| update(state, fetched));
+    let mut wanted = false;
+    for event in applied {
+        wanted |= apply(snapshot, &mut pending.refetch, event);
+    }
+    Ok(wanted)
+}
+
+// changes a known player, events for unknown players are dropped.
+fn update_player<F: FnOnce(&mut PlayerState)>(
+    snapshot: &watch::Sender<Snapshot>,
+    pid: PlayerId,
+    update: F,
+) {
+    snapshot.send_if_modified(|state| match state.players.get_mut(&pid) {
+        Some(player) => {
+            update(player);
+            true
+        }
+        None => {
+            debug!("ignoring event for unknown player {}", pid);
+            false
+        }
+    });
+}
+
+// the mode events only carry one half of the play mode, the whole mode is
+// fetched if it isn't known yet.
+fn update_play_mode<F: FnOnce(&mut PlayMode)>(
+    snapshot: &watch::Sender<Snapshot>,
+    refetch: &mut Refetch,
+    pid: PlayerId,
+    update: F,
+) {
+    let known = snapshot
+        .borrow()
+        .players
+        .get(&pid)
+        .and_then(|player| player.play_mode);
+    match known {
+        Some(mut play_mode) => {
+            update(&mut play_mode);
+            update_player(snapshot, pid, |player| player.play_mode = Some(play_mode));
+        }
+        None => {
+            refetch.play_modes.insert(pid);
+        }
+    }
+}
+
+async fn fetch_all(client: &HeosClient) -> HeosResult<Snapshot> {
+    Ok(Snapshot {
+        players: fetch_players(client).await?,
+        groups: fetch_groups(client).await?,
+    })
+}
+
+async fn fetch_players(client: &HeosClient) -> HeosResult<BTreeMap<PlayerId, PlayerState>> {
+    let mut players = BTreeMap::new();
+    for info in client.execute_command(GetPlayers).await? {
+        let pid = info.pid;
+        players.insert(pid, fetch_player(client, info).await);
+    }
+    Ok(players)
+}
+
+// a player that went away while being fetched is kept with what is known.
+async fn fetch_player(client: &HeosClient, info: PlayerInfo) -> PlayerState {
+    let pid = info.pid;
+    PlayerState {
+        info,
+        volume: ok_or_warn(client.execute_command(GetPlayerVolume { pid }).await),
+        mute: ok_or_warn(client.execute_command(GetPlayerMute { pid }).await),
+        play_state: ok_or_warn(client.execute_command(GetPlayState { pid }).await),
+        play_mode: ok_or_warn(client.execute_command(GetPlayMode { pid }).await),
+        now_playing: ok_or_warn(fetch_now_playing(client, pid).await),
+        progress: None,
+    }
+}
+
+async fn fetch_now_playing(client: &HeosClient, pid: PlayerId) -> HeosResult<NowPlaying> {
+    client.execute_command(GetNowPlayingMedia { pid }).await
+}
+
+async fn fetch_groups(client: &HeosClient) -> HeosResult<BTreeMap<GroupId, GroupState>> {
+    let mut groups = BTreeMap::new();
+    for info in client.execute_command(GetGroups).await? {
+        let gid = info.gid;
+        let group = GroupState {
+            info,
+            volume: ok_or_warn(client.execute_command(GetGroupVolume { gid }).await),
+            mute: ok_or_warn(client.execute_command(GetGroupMute { gid }).await),
+        };
+        groups.insert(gid, group);
+    }
+    Ok(groups)
+}
+
+fn ok_or_warn<T>(result: HeosResult<T>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            warn!("could not fetch state: {}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::mock::{MockHeos, MockReply};
+    use crate::NowPlayingMedia;
+
+    // waits until the snapshot satisfies `done`.
+    async fn until<F: Fn(&Snapshot) -> bool>(state: &HeosState, done: F) {
+        let mut snapshot = state.subscribe();
+        let wait = snapshot.wait_for(|snapshot| done(snapshot));
+        tokio::time::timeout(Duration::from_secs(2), wait)
+            .await
+            .expect("state wasn't updated in time")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_are_applied_while_fetching() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = HeosClient::connect(mock.address()).await.unwrap();
+        let state = HeosState::start(client).await.unwrap();
+        assert_eq!(state.player(1).unwrap().volume, Some(25));
+
+        // the fetch triggered by `players_changed` never finishes.
+        mock.on("player/get_players", MockReply::Silent);
+        mock.send_event("players_changed", "");
+        mock.send_event("player_volume_changed", "pid=1&level=40&mute=on");
+        until(&state, |snapshot| {
+            snapshot.players[&1].volume == Some(40)
+                && snapshot.players[&1].mute == Some(OnOrOff::On)
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn events_during_a_fetch_are_not_lost() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = HeosClient::connect(mock.address()).await.unwrap();
+        let state = HeosState::start(client).await.unwrap();
+
+        // the living room is renamed, and its volume changes while the
+        // players are fetched again.
+        let players = serde_json::json!([
+            { "name": "Den", "pid": 1 },
+            { "name": "Kitchen", "pid": 2 }
+        ]);
+        mock.on("player/get_players", MockReply::payload(players));
+        let media = serde_json::json!({ "type": "song", "song": "Song", "sid": 1024 });
+        let slow = MockReply::payload(media).after(Duration::from_millis(200));
+        mock.once("player/get_now_playing_media", slow);
+        mock.send_event("players_changed", "");
+        let fetching = async {
+            // the volume was fetched again already, the media not yet.
+            let asked = |mock: &MockHeos| {
+                let received = mock.received();
+                let media = "player/get_now_playing_media?pid=1";
+                received
+                    .iter()
+                    .filter(|line| line.starts_with(media))
+                    .count()
+            };
+            while asked(&mock) < 2 {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(2), fetching)
+            .await
+            .expect("players weren't fetched again");
+        mock.send_event("player_volume_changed", "pid=1&level=40&mute=off");
+
+        until(&state, |snapshot| {
+            let player = &snapshot.players[&1];
+            player.info.name == "Den" && player.volume == Some(40)
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn now_playing_is_fetched_again() {
+        let mock = MockHeos::start().await.unwrap();
+        let client = HeosClient::connect(mock.address()).await.unwrap();
+        let state = HeosState::start(client).await.unwrap();
+
+        mock.on(
+            "player/get_now_playing_media",
+            MockReply::payload(serde_json::json!({
+                "type": "station",
+                "song": "Song",
+                "station": "Radio",
+                "album": "",
+                "artist": "Artist",
+                "image_url": "",
+                "album_id": "",
+                "mid": "s1",
+                "qid": 1,
+                "sid": 3
+            })),
+        );
+        mock.send_event("player_now_playing_changed", "pid=2");
+        until(&state, |snapshot| {
+            let media = snapshot.players[&2]
+                .now_playing
+                .as_ref()
+                .and_then(|now_playing| now_playing.media.as_ref());
+            media.map(NowPlayingMedia::sid) == Some(3)
+        })
+        .await;
+    }
+}
@@ -1,3 +1,7 @@
+use crate::HeosResult;
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
 use serde_json::Value as Json;
 use std::fmt;
 
@@ -9,7 +13,13 @@ pub type AlbumId = String;
 pub type MediaId = String;
 pub type ContainerId = String;
 pub type Level = u8;
+pub type Step = u8;
+pub type QuickSelectId = u8;
+pub type PresetId = u32;
+pub type SearchCriteriaId = i64;
 pub type Milliseconds = u64;
+// the `SEQUENCE` a command is tagged with and its answer echoes.
+pub type Sequence = u64;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Eq, PartialEq)]
 pub enum PlayState {
@@ -29,13 +39,32 @@ pub enum OnOrOff {
     Off,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Repeat {
+    #[serde(rename = "off")]
     Off,
+    #[serde(rename = "on_one")]
     OnOne,
+    #[serde(rename = "on_all")]
     OnAll,
 }
 
+// the `range=start,end` window used by every paged command; both ends are inclusive.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: u32,
+    pub end: u32,
+}
+
+// what `browse/add_to_queue` does with the added media.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddToQueueAid {
+    PlayNow = 1,
+    PlayNext = 2,
+    AddToEnd = 3,
+    ReplaceAndPlay = 4,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum CommandResult {
     Success,
@@ -48,6 +77,253 @@ pub struct CommandResponse {
     pub message: Json, //
     pub payload: Json, // can be Null
     pub options: Json, // can be Null
+    // `None` if the command wasn't tagged with one.
+    #[serde(default)]
+    pub sequence: Option<Sequence>,
+}
+
+impl CommandResponse {
+    // deserializes the payload, e.g. the items of `player/get_queue`.
+    pub fn parse_payload<T: DeserializeOwned>(&self) -> HeosResult<T> {
+        let payload = serde_json::from_value(self.payload.clone())
+            .context(format!("could not parse payload of {}", &self.command_name))?;
+        Ok(payload)
+    }
+
+    // deserializes the parsed message, e.g. `pid=1&repeat=on_all&shuffle=off`.
+    pub fn parse_message<T: DeserializeOwned>(&self) -> HeosResult<T> {
+        let message = serde_json::from_value(self.message.clone())
+            .context(format!("could not parse message of {}", &self.command_name))?;
+        Ok(message)
+    }
+}
+
+// a player as returned by `player/get_players` and `player/get_player_info`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlayerInfo {
+    #[serde(deserialize_with = "lenient::string")]
+    pub name: String,
+    #[serde(deserialize_with = "lenient::number")]
+    pub pid: PlayerId,
+    // only set while the player is part of a group.
+    #[serde(default, deserialize_with = "lenient::optional_number")]
+    pub gid: Option<GroupId>,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub model: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub version: String,
+    #[serde(default)]
+    pub network: Network,
+    #[serde(default)]
+    pub lineout: Option<LineOut>,
+    // how a fixed line out is controlled, only sent for those.
+    #[serde(default)]
+    pub control: Option<Control>,
+    #[serde(default, deserialize_with = "lenient::optional_string")]
+    pub ip: Option<String>,
+    #[serde(default, deserialize_with = "lenient::optional_string")]
+    pub serial: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Network {
+    Wired,
+    Wifi,
+    #[default]
+    #[serde(other)]
+    Unknown,
+}
+
+// sent as `1` or `2`, anything else ends up as `Unknown`.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LineOut {
+    Variable,
+    Fixed,
+    Unknown,
+}
+
+// sent as `1` to `4`, anything else ends up as `Unknown`.
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Control {
+    None,
+    Ir,
+    Trigger,
+    Network,
+    Unknown,
+}
+
+// a group as returned by `group/get_groups` and `group/get_group_info`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GroupInfo {
+    #[serde(deserialize_with = "lenient::string")]
+    pub name: String,
+    #[serde(deserialize_with = "lenient::number")]
+    pub gid: GroupId,
+    #[serde(default)]
+    pub players: Vec<GroupMember>,
+}
+
+impl GroupInfo {
+    pub fn leader(&self) -> Option<&GroupMember> {
+        self.players
+            .iter()
+            .find(|member| member.role == GroupRole::Leader)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct GroupMember {
+    #[serde(deserialize_with = "lenient::string")]
+    pub name: String,
+    #[serde(deserialize_with = "lenient::number")]
+    pub pid: PlayerId,
+    pub role: GroupRole,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupRole {
+    Leader,
+    Member,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlayMode {
+    pub repeat: Repeat,
+    pub shuffle: OnOrOff,
+}
+
+// one entry of a player's queue as returned by `player/get_queue`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct QueueItem {
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub song: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub album: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub artist: String,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub image_url: String,
+    #[serde(deserialize_with = "lenient::number")]
+    pub qid: QueueId,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub mid: MediaId,
+    #[serde(default, deserialize_with = "lenient::string")]
+    pub album_id: AlbumId,
+}
+
+// the answer of `system/check_account`, `system/sign_in` and `system/sign_out`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccountStatus {
+    SignedIn(String),
+    SignedOut,
+}
+
+// what `player/get_now_playing_media` answers with; `media` is `None` if
+// nothing is playing.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct NowPlaying {
+    pub media: Option<NowPlayingMedia>,
+    // what can be done with the media, e.g. `ThumbsUp`.
+    pub options: Vec<ServiceOption>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NowPlayingMedia {
+    Song {
+        #[serde(default, deserialize_with = "lenient::string")]
+        song: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        album: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        artist: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        image_url: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        album_id: AlbumId,
+        #[serde(default, deserialize_with = "lenient::string")]
+        mid: MediaId,
+        // only set when playing from the queue.
+        #[serde(default, deserialize_with = "lenient::optional_number")]
+        qid: Option<QueueId>,
+        #[serde(deserialize_with = "lenient::number")]
+        sid: SourceId,
+    },
+    // `song` and `artist` are whatever the station currently plays.
+    Station {
+        #[serde(default, deserialize_with = "lenient::string")]
+        station: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        song: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        album: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        artist: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        image_url: String,
+        #[serde(default, deserialize_with = "lenient::string")]
+        album_id: AlbumId,
+        #[serde(default, deserialize_with = "lenient::string")]
+        mid: MediaId,
+        #[serde(default, deserialize_with = "lenient::optional_number")]
+        qid: Option<QueueId>,
+        #[serde(deserialize_with = "lenient::number")]
+        sid: SourceId,
+    },
+}
+
+impl NowPlayingMedia {
+    pub fn song(&self) -> &str {
+        match self {
+            NowPlayingMedia::Song { song, .. } | NowPlayingMedia::Station { song, .. } => song,
+        }
+    }
+
+    pub fn artist(&self) -> &str {
+        match self {
+            NowPlayingMedia::Song { artist, .. } | NowPlayingMedia::Station { artist, .. } => {
+                artist
+            }
+        }
+    }
+
+    pub fn album(&self) -> &str {
+        match self {
+            NowPlayingMedia::Song { album, .. } | NowPlayingMedia::Station { album, .. } => album,
+        }
+    }
+
+    pub fn sid(&self) -> SourceId {
+        match self {
+            NowPlayingMedia::Song { sid, .. } | NowPlayingMedia::Station { sid, .. } => *sid,
+        }
+    }
+}
+
+// the options heos offers for media, by their `id`. They can be used with
+// `ServiceOptionAction`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceOption {
+    AddTrackToLibrary,
+    AddAlbumToLibrary,
+    AddStationToLibrary,
+    AddPlaylistToLibrary,
+    RemoveTrackFromLibrary,
+    RemoveAlbumFromLibrary,
+    RemoveStationFromLibrary,
+    RemovePlaylistFromLibrary,
+    ThumbsUp,
+    ThumbsDown,
+    CreateNewStation,
+    AddToFavorites,
+    RemoveFromFavorites,
+    Other { id: i64, name: String },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -56,12 +332,6 @@ pub struct ErrorResponse {
     pub message: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct EventResponse {
-    pub event_name: String,
-    pub message: Json
-}
-
 //////
 impl fmt::Display for OnOrOff {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -69,8 +339,8 @@ impl fmt::Display for OnOrOff {
             f,
             "{}",
             match self {
-                &OnOrOff::Off => "off",
-                &OnOrOff::On => "on",
+                OnOrOff::Off => "off",
+                OnOrOff::On => "on",
             }
         )
     }
@@ -79,11 +349,11 @@ impl std::str::FromStr for OnOrOff {
     type Err = String;
 
     fn from_str(string: &str) -> Result<OnOrOff, String> {
-        return match string {
+        match string {
             "on" => Ok(OnOrOff::On),
             "off" => Ok(OnOrOff::Off),
             c => Err(format!("can't convert {} to OnOff", c)),
-        };
+        }
     }
 }
 impl fmt::Display for PlayState {
@@ -92,10 +362,271 @@ impl fmt::Display for PlayState {
             f,
             "{}",
             match self {
-                &PlayState::Play => "play",
-                &PlayState::Pause => "pause",
-                &PlayState::Stop => "stop",
+                PlayState::Play => "play",
+                PlayState::Pause => "pause",
+                PlayState::Stop => "stop",
+            }
+        )
+    }
+}
+impl fmt::Display for Repeat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Repeat::Off => "off",
+                Repeat::OnOne => "on_one",
+                Repeat::OnAll => "on_all",
             }
         )
     }
 }
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{},{}", self.start, self.end)
+    }
+}
+impl fmt::Display for AddToQueueAid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+impl<'de> Deserialize<'de> for LineOut {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(
+            match lenient::NumberOrString::deserialize(deserializer)?.code() {
+                Some(1) => LineOut::Variable,
+                Some(2) => LineOut::Fixed,
+                _ => LineOut::Unknown,
+            },
+        )
+    }
+}
+impl<'de> Deserialize<'de> for Control {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(
+            match lenient::NumberOrString::deserialize(deserializer)?.code() {
+                Some(1) => Control::None,
+                Some(2) => Control::Ir,
+                Some(3) => Control::Trigger,
+                Some(4) => Control::Network,
+                _ => Control::Unknown,
+            },
+        )
+    }
+}
+
+// an option as heos sends it, `{"id": 11, "name": "Thumbs Up"}`.
+#[derive(Deserialize)]
+pub(crate) struct RawServiceOption {
+    #[serde(deserialize_with = "lenient::number")]
+    id: i64,
+    #[serde(default, deserialize_with = "lenient::string")]
+    name: String,
+}
+
+impl From<RawServiceOption> for ServiceOption {
+    fn from(raw: RawServiceOption) -> Self {
+        match raw.id {
+            1 => ServiceOption::AddTrackToLibrary,
+            2 => ServiceOption::AddAlbumToLibrary,
+            3 => ServiceOption::AddStationToLibrary,
+            4 => ServiceOption::AddPlaylistToLibrary,
+            5 => ServiceOption::RemoveTrackFromLibrary,
+            6 => ServiceOption::RemoveAlbumFromLibrary,
+            7 => ServiceOption::RemoveStationFromLibrary,
+            8 => ServiceOption::RemovePlaylistFromLibrary,
+            11 => ServiceOption::ThumbsUp,
+            12 => ServiceOption::ThumbsDown,
+            13 => ServiceOption::CreateNewStation,
+            19 => ServiceOption::AddToFavorites,
+            20 => ServiceOption::RemoveFromFavorites,
+            id => ServiceOption::Other { id, name: raw.name },
+        }
+    }
+}
+
+// heos isn't consistent about numbers and strings, e.g. a `pid` can come as
+// `-1428708007` or `"-1428708007"` depending on the firmware.
+mod lenient {
+    use serde::{de::Error, Deserialize, Deserializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    pub enum NumberOrString {
+        Number(i64),
+        Float(f64),
+        String(String),
+    }
+
+    impl NumberOrString {
+        pub fn number(&self) -> Option<i64> {
+            match self {
+                NumberOrString::Number(number) => Some(*number),
+                NumberOrString::Float(_) => None,
+                NumberOrString::String(string) => string.trim().parse().ok(),
+            }
+        }
+
+        // the number behind codes like `lineout`, also accepting the names
+        // they are serialized with.
+        pub fn code(&self) -> Option<i64> {
+            match self {
+                NumberOrString::String(name) => match name.as_str() {
+                    "variable" | "none" => Some(1),
+                    "fixed" | "ir" => Some(2),
+                    "trigger" => Some(3),
+                    "network" => Some(4),
+                    _ => self.number(),
+                },
+                _ => self.number(),
+            }
+        }
+
+        fn into_string(self) -> String {
+            match self {
+                NumberOrString::Number(number) => number.to_string(),
+                NumberOrString::Float(number) => number.to_string(),
+                NumberOrString::String(string) => string,
+            }
+        }
+    }
+
+    pub fn number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        let value = NumberOrString::deserialize(deserializer)?;
+        value.number().ok_or_else(|| {
+            D::Error::custom(format!("expected a number, got {}", value.into_string()))
+        })
+    }
+
+    pub fn optional_number<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<i64>, D::Error> {
+        // an empty string means no value as well.
+        match Option::<NumberOrString>::deserialize(deserializer)? {
+            Some(NumberOrString::String(string)) if string.trim().is_empty() => Ok(None),
+            Some(value) => value
+                .number()
+                .map(Some)
+                .ok_or_else(|| D::Error::custom("expected a number")),
+            None => Ok(None),
+        }
+    }
+
+    pub fn string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+        Ok(NumberOrString::deserialize(deserializer)?.into_string())
+    }
+
+    pub fn optional_string<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<String>, D::Error> {
+        Ok(Option::<NumberOrString>::deserialize(deserializer)?.map(NumberOrString::into_string))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn queue_items_with_numbers_and_strings() {
+        let items: Vec<QueueItem> = serde_json::from_value(json!([
+            {
+                "song": "Song",
+                "album": "Album",
+                "artist": "Artist",
+                "image_url": "",
+                "qid": 1,
+                "mid": "1234",
+                "album_id": "56"
+            },
+            { "song": 1999, "qid": "2", "mid": 1234, "album_id": 56 }
+        ]))
+        .unwrap();
+        assert_eq!(items[0].qid, 1);
+        assert_eq!(items[1].qid, 2);
+        assert_eq!(items[1].song, "1999");
+        assert_eq!(items[1].mid, "1234");
+        assert_eq!(items[1].album_id, "56");
+        assert_eq!(items[1].artist, "");
+    }
+
+    #[test]
+    fn players_with_numbers_and_strings() {
+        let players: Vec<PlayerInfo> = serde_json::from_value(json!([
+            {
+                "name": "Living Room",
+                "pid": 1,
+                "gid": -5,
+                "model": "HEOS 7",
+                "version": "1.520.200",
+                "network": "wired",
+                "lineout": 0,
+                "ip": "192.168.1.10",
+                "serial": "AAA0000001"
+            },
+            { "name": "Kitchen", "pid": "-1428708007", "gid": "", "network": "wifi" },
+            { "name": 42, "pid": 3, "network": "bluetooth" }
+        ]))
+        .unwrap();
+        assert_eq!(players[0].gid, Some(-5));
+        assert_eq!(players[0].network, Network::Wired);
+        assert_eq!(players[0].ip.as_deref(), Some("192.168.1.10"));
+        assert_eq!(players[1].pid, -1428708007);
+        assert_eq!(players[1].gid, None);
+        assert_eq!(players[1].network, Network::Wifi);
+        assert_eq!(players[2].name, "42");
+        assert_eq!(players[2].gid, None);
+        assert_eq!(players[2].network, Network::Unknown);
+        assert_eq!(players[2].lineout, None);
+    }
+
+    #[test]
+    fn line_out_and_control_codes() {
+        let player = |lineout: Json, control: Json| -> PlayerInfo {
+            let json = json!({
+                "name": "Amp",
+                "pid": 1,
+                "lineout": lineout,
+                "control": control
+            });
+            serde_json::from_value(json).unwrap()
+        };
+        let cases = [
+            (json!(1), json!(1), LineOut::Variable, Control::None),
+            (json!(2), json!(2), LineOut::Fixed, Control::Ir),
+            (json!("2"), json!("3"), LineOut::Fixed, Control::Trigger),
+            (json!(2), json!(4), LineOut::Fixed, Control::Network),
+            (json!(7), json!(9), LineOut::Unknown, Control::Unknown),
+            (json!("x"), json!(0), LineOut::Unknown, Control::Unknown),
+        ];
+        for (lineout, control, expected_lineout, expected_control) in cases {
+            let player = player(lineout, control);
+            assert_eq!(player.lineout, Some(expected_lineout));
+            assert_eq!(player.control, Some(expected_control));
+        }
+    }
+
+    #[test]
+    fn group_leader() {
+        let group: GroupInfo = serde_json::from_value(json!({
+            "name": "Living Room + Kitchen",
+            "gid": "-5",
+            "players": [
+                { "name": "Kitchen", "pid": 2, "role": "member" },
+                { "name": "Living Room", "pid": "1", "role": "leader" }
+            ]
+        }))
+        .unwrap();
+        assert_eq!(group.gid, -5);
+        assert_eq!(group.leader().map(|leader| leader.pid), Some(1));
+
+        let leaderless = GroupInfo {
+            players: group.players[..1].to_vec(),
+            ..group
+        };
+        assert!(leaderless.leader().is_none());
+    }
+}